@@ -8,4 +8,183 @@ where
 {
     let file = File::open(filename)?;
     Ok(io::BufReader::new(file).lines())
-}
\ No newline at end of file
+}
+
+/// Set of integers kept as sorted, disjoint, inclusive ranges.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IntervalSet {
+    ranges: Vec<(i64, i64)>,
+}
+
+impl IntervalSet {
+    pub fn new() -> IntervalSet {
+        IntervalSet { ranges: Vec::new() }
+    }
+
+    pub fn from_ranges(ranges: &[(i64, i64)]) -> IntervalSet {
+        let mut set = IntervalSet::new();
+        for &(start, end) in ranges {
+            set.insert(start, end);
+        }
+        set
+    }
+
+    pub fn ranges(&self) -> &[(i64, i64)] {
+        &self.ranges
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn contains(&self, value: i64) -> bool {
+        let idx = self.ranges.partition_point(|&(_, e)| e < value);
+        idx < self.ranges.len() && self.ranges[idx].0 <= value
+    }
+
+    /// Number of integers in the set
+    pub fn len(&self) -> u64 {
+        self.ranges.iter().map(|(s, e)| (e - s + 1) as u64).sum()
+    }
+
+    pub fn insert(&mut self, start: i64, end: i64) {
+        if start > end {
+            return;
+        }
+        // first range that touches or lies after the new one
+        let lo = self.ranges.partition_point(|&(_, e)| e < start.saturating_sub(1));
+        // first range that lies entirely after the new one
+        let hi = self.ranges.partition_point(|&(s, _)| s <= end.saturating_add(1));
+        let mut merged = (start, end);
+        if lo < hi {
+            merged.0 = merged.0.min(self.ranges[lo].0);
+            merged.1 = merged.1.max(self.ranges[hi - 1].1);
+        }
+        self.ranges.splice(lo..hi, [merged]);
+    }
+
+    pub fn union(&self, other: &IntervalSet) -> IntervalSet {
+        let mut ret = self.clone();
+        for &(start, end) in &other.ranges {
+            ret.insert(start, end);
+        }
+        ret
+    }
+
+    pub fn intersection(&self, other: &IntervalSet) -> IntervalSet {
+        let mut ranges = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < self.ranges.len() && j < other.ranges.len() {
+            let (a_start, a_end) = self.ranges[i];
+            let (b_start, b_end) = other.ranges[j];
+            let start = a_start.max(b_start);
+            let end = a_end.min(b_end);
+            if start <= end {
+                ranges.push((start, end));
+            }
+            if a_end < b_end {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        IntervalSet { ranges }
+    }
+
+    pub fn difference(&self, other: &IntervalSet) -> IntervalSet {
+        let mut ranges = Vec::new();
+        let mut j = 0;
+        for &(start, end) in &self.ranges {
+            let mut cur = start;
+            while j < other.ranges.len() && other.ranges[j].1 < cur {
+                j += 1;
+            }
+            let mut k = j;
+            while cur <= end && k < other.ranges.len() && other.ranges[k].0 <= end {
+                let (b_start, b_end) = other.ranges[k];
+                if b_start > cur {
+                    ranges.push((cur, b_start - 1));
+                }
+                cur = cur.max(b_end.saturating_add(1));
+                k += 1;
+            }
+            if cur <= end {
+                ranges.push((cur, end));
+            }
+        }
+        IntervalSet { ranges }
+    }
+
+    /// Splits the covered line into segments labelled with how many of the
+    /// input ranges cover them. Uncovered gaps are not reported.
+    pub fn coverage(ranges: &[(i64, i64)]) -> Vec<((i64, i64), usize)> {
+        let mut events: Vec<(i64, isize)> = Vec::new();
+        for &(start, end) in ranges {
+            if start <= end {
+                events.push((start, 1));
+                events.push((end + 1, -1));
+            }
+        }
+        events.sort_unstable();
+
+        let mut ret = Vec::new();
+        let mut depth: isize = 0;
+        let mut idx = 0;
+        while idx < events.len() {
+            let pos = events[idx].0;
+            while idx < events.len() && events[idx].0 == pos {
+                depth += events[idx].1;
+                idx += 1;
+            }
+            if depth > 0 && idx < events.len() {
+                ret.push(((pos, events[idx].0 - 1), depth as usize));
+            }
+        }
+        ret
+    }
+
+    /// Integers covered by more than `count` of the input ranges
+    pub fn covered_more_than(ranges: &[(i64, i64)], count: usize) -> IntervalSet {
+        let mut ret = IntervalSet::new();
+        for ((start, end), depth) in IntervalSet::coverage(ranges) {
+            if depth > count {
+                ret.insert(start, end);
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod _tests {
+    use super::IntervalSet;
+    #[test]
+    fn test_interval_insert_merges() {
+        let set = IntervalSet::from_ranges(&[(5, 7), (1, 2), (3, 4), (10, 12), (11, 20)]);
+        assert_eq!(set.ranges(), &[(1, 7), (10, 20)]);
+        assert_eq!(set.len(), 18);
+        assert!(set.contains(7));
+        assert!(!set.contains(8));
+    }
+    #[test]
+    fn test_interval_set_operations() {
+        let a = IntervalSet::from_ranges(&[(0, 10), (20, 30)]);
+        let b = IntervalSet::from_ranges(&[(5, 25)]);
+        assert_eq!(a.union(&b).ranges(), &[(0, 30)]);
+        assert_eq!(a.intersection(&b).ranges(), &[(5, 10), (20, 25)]);
+        assert_eq!(a.difference(&b).ranges(), &[(0, 4), (26, 30)]);
+        assert_eq!(b.difference(&a).ranges(), &[(11, 19)]);
+    }
+    #[test]
+    fn test_interval_coverage() {
+        let ranges = [(2, 4), (6, 8), (3, 7)];
+        assert_eq!(
+            IntervalSet::coverage(&ranges),
+            vec![((2, 2), 1), ((3, 4), 2), ((5, 5), 1), ((6, 7), 2), ((8, 8), 1)]
+        );
+        assert_eq!(
+            IntervalSet::covered_more_than(&ranges, 1).ranges(),
+            &[(3, 4), (6, 7)]
+        );
+    }
+}
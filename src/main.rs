@@ -9,6 +9,7 @@ mod task07;
 mod task08;
 mod task09;
 mod task10;
+mod task10ocr;
 mod task11;
 mod task12;
 mod task13;
@@ -1,23 +1,53 @@
+use std::fmt;
 use std::path::PathBuf;
 
-use clap::{value_parser, ArgMatches, Command};
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
 
 use crate::common;
 
+#[derive(PartialEq, Eq, Debug)]
+enum RucksackIssue {
+    OddLength(usize),
+    InvalidItems(Vec<char>),
+    NoCommonItem,
+    MultipleCommonItems(Vec<char>),
+}
+
+impl fmt::Display for RucksackIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RucksackIssue::OddLength(len) => write!(f, "odd length {}, compartments split unevenly", len),
+            RucksackIssue::InvalidItems(items) => write!(f, "invalid item types {:?}", items),
+            RucksackIssue::NoCommonItem => write!(f, "no item common to both compartments"),
+            RucksackIssue::MultipleCommonItems(items) => write!(f, "more than one common item {:?}", items),
+        }
+    }
+}
+
 pub fn cli() -> Command {
-    Command::new("day03").about("Elvish backpack mistakes").arg(
-        clap::arg!(path: <PATH>)
-            .required(true)
-            .value_parser(value_parser!(std::path::PathBuf)),
-    )
+    Command::new("day03")
+        .about("Elvish backpack mistakes")
+        .arg(
+            Arg::new("strict")
+                .short('s')
+                .long("strict")
+                .help("Fail the run if any rucksack is malformed")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            clap::arg!(path: <PATH>)
+                .required(true)
+                .value_parser(value_parser!(std::path::PathBuf)),
+        )
 }
 
 pub fn handle(matches: &ArgMatches) {
     let path = matches.get_one::<std::path::PathBuf>("path");
-    solve(path.unwrap().to_path_buf());
+    let strict = matches.get_flag("strict");
+    solve(path.unwrap().to_path_buf(), strict);
 }
 
-fn solve(filepath: PathBuf) {
+fn solve(filepath: PathBuf, strict: bool) {
     let mut acc_split: u32 = 0;
     let mut acc_badge: u32 = 0;
 
@@ -26,18 +56,24 @@ fn solve(filepath: PathBuf) {
     let mut bak_line1: String = String::new();
     let mut bak_line2: String = String::new();
 
+    let mut report: Vec<(usize, RucksackIssue)> = Vec::new();
+
     if let Ok(lines) = common::read_lines(filepath) {
-        for line in lines {
+        for (line_no, line) in lines.enumerate() {
             if let Ok(line_text) = line {
+                for issue in validate_rucksack(&line_text) {
+                    report.push((line_no + 1, issue));
+                }
 
                 let (sub1, sub2) = split_str(&line_text);
-                match find_common_char(&sub1, &sub2){
-                    Some(cc) => {acc_split += score(cc)},
-                    None => {}
+                if let Some(cc) = find_common_char(&sub1, &sub2) {
+                    acc_split += score(cc).unwrap_or(0);
                 }
                 index = (index + 1) % 3;
                 if index == 0 {
-                    acc_badge += score(find_common_char3(&bak_line1, &bak_line2, &line_text).unwrap())
+                    if let Some(badge) = find_common_char3(&bak_line1, &bak_line2, &line_text) {
+                        acc_badge += score(badge).unwrap_or(0);
+                    }
                 }
                 else if index == 1 {
                     bak_line1 = line_text;
@@ -49,7 +85,44 @@ fn solve(filepath: PathBuf) {
 
         }
     }
-    println!("Final score {}/{}", acc_split, acc_badge)
+    println!("Final score {}/{}", acc_split, acc_badge);
+
+    if !report.is_empty() {
+        println!("Validation report:");
+        for (line_no, issue) in &report {
+            println!("line {}: {}", line_no, issue);
+        }
+        if strict {
+            eprintln!("{} malformed rucksack entries found", report.len());
+            std::process::exit(1);
+        }
+    }
+}
+
+fn validate_rucksack(line: &str) -> Vec<RucksackIssue> {
+    let mut issues = Vec::new();
+    let len = line.chars().count();
+    if len % 2 == 1 {
+        issues.push(RucksackIssue::OddLength(len));
+    }
+
+    let mut invalid: Vec<char> = line.chars().filter(|c| score(*c).is_none()).collect();
+    invalid.sort_unstable();
+    invalid.dedup();
+    if !invalid.is_empty() {
+        issues.push(RucksackIssue::InvalidItems(invalid));
+    }
+
+    let (sub1, sub2) = split_str(line);
+    let mut common: Vec<char> = sub1.chars().filter(|c| sub2.contains(*c)).collect();
+    common.sort_unstable();
+    common.dedup();
+    match common.len() {
+        0 => issues.push(RucksackIssue::NoCommonItem),
+        1 => {}
+        _ => issues.push(RucksackIssue::MultipleCommonItems(common)),
+    }
+    issues
 }
 
 fn find_common_char(input1:&String, input2:&String) -> Option<char>{
@@ -71,15 +144,53 @@ fn find_common_char3(input1:&str, input2:&str, input3:&str) -> Option<char>{
 }
 
 fn split_str(input: &str) -> (String, String){
-    let half_length = input.len()/2;
-    return (String::from(&input[..half_length]) , String::from(&input[half_length..])) ;
+    let half_length = input.chars().count()/2;
+    let split_at = input.char_indices().nth(half_length).map_or(input.len(), |(i, _)| i);
+    (String::from(&input[..split_at]), String::from(&input[split_at..]))
 }
 
-fn score(item: char) -> u32 {
+fn score(item: char) -> Option<u32> {
     if item.is_ascii_uppercase() {
-       return item as u32 - 38;
+       Some(item as u32 - 38)
+    }
+    else if item.is_ascii_lowercase() {
+        Some(item as u32 - 96)
     }
     else {
-        return item as u32 - 96;
+        None
+    }
+}
+
+#[cfg(test)]
+mod _tests {
+    use super::{score, validate_rucksack, RucksackIssue};
+    #[test]
+    fn test_score() {
+        assert_eq!(score('a'), Some(1));
+        assert_eq!(score('Z'), Some(52));
+        assert_eq!(score('1'), None);
+        assert_eq!(score('ł'), None);
+    }
+    #[test]
+    fn test_validate_rucksack_ok() {
+        assert_eq!(validate_rucksack("vJrwpWtwJgWrhcsFMMfFFhFp"), vec![]);
+    }
+    #[test]
+    fn test_validate_rucksack_issues() {
+        assert_eq!(
+            validate_rucksack("abcde"),
+            vec![RucksackIssue::OddLength(5), RucksackIssue::NoCommonItem]
+        );
+        assert_eq!(
+            validate_rucksack("a1ba1b"),
+            vec![
+                RucksackIssue::InvalidItems(vec!['1']),
+                RucksackIssue::MultipleCommonItems(vec!['1', 'a', 'b'])
+            ]
+        );
+        assert_eq!(
+            validate_rucksack("1!a1"),
+            vec![RucksackIssue::InvalidItems(vec!['!', '1'])]
+        );
     }
 }
@@ -1,20 +1,42 @@
 use std::path::PathBuf;
 
-use clap::{value_parser, ArgMatches, Command};
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
 
-use crate::common;
+use crate::common::{self, IntervalSet};
 
 pub fn cli() -> Command {
-    Command::new("day04").about("Elvish camp cleanup").arg(
-        clap::arg!(path: <PATH>)
-            .required(true)
-            .value_parser(value_parser!(std::path::PathBuf)),
-    )
+    Command::new("day04")
+        .about("Elvish camp cleanup")
+        .arg(
+            Arg::new("coverage")
+                .short('c')
+                .long("coverage")
+                .help("Report section coverage across all elves and whole groups")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("more-than")
+                .short('k')
+                .long("more-than")
+                .help("Report sections cleaned by more than K elves")
+                .value_parser(value_parser!(usize))
+                .default_value("1"),
+        )
+        .arg(
+            clap::arg!(path: <PATH>)
+                .required(true)
+                .value_parser(value_parser!(std::path::PathBuf)),
+        )
 }
 
 pub fn handle(matches: &ArgMatches) {
     let path = matches.get_one::<std::path::PathBuf>("path");
-    solve(path.unwrap().to_path_buf());
+    if matches.get_flag("coverage") {
+        let more_than = *matches.get_one::<usize>("more-than").unwrap();
+        solve_coverage(path.unwrap().to_path_buf(), more_than);
+    } else {
+        solve(path.unwrap().to_path_buf());
+    }
 }
 
 fn solve(filepath: PathBuf) {
@@ -59,9 +81,96 @@ fn line_to_range_pair(line: &str) -> ((u32, u32), (u32, u32)) {
     return ((sub_split[0], sub_split[1]), (sub_split[2], sub_split[3]));
 }
 
+fn solve_coverage(filepath: PathBuf, more_than: usize) {
+    let mut assignments: Vec<(i64, i64)> = Vec::new();
+    let mut cleaned = IntervalSet::new();
+    let mut group_count: u32 = 0;
+    let mut acc_contained: u32 = 0;
+    let mut acc_any: u32 = 0;
+    let mut acc_shared: u32 = 0;
+
+    if let Ok(lines) = common::read_lines(filepath) {
+        for line in lines.map_while(Result::ok) {
+            if line.is_empty() {
+                continue;
+            }
+            let group: Vec<(i64, i64)> = line_to_ranges(&line)
+                .iter()
+                .map(|&(s, e)| (s as i64, e as i64))
+                .collect();
+            let members: Vec<IntervalSet> = group.iter().map(|r| IntervalSet::from_ranges(&[*r])).collect();
+            let group_union = members.iter().fold(IntervalSet::new(), |acc, m| acc.union(m));
+            let group_shared = members
+                .iter()
+                .skip(1)
+                .fold(members[0].clone(), |acc, m| acc.intersection(m));
+
+            group_count += 1;
+            if members.iter().any(|m| group_union.difference(m).is_empty()) {
+                acc_contained += 1;
+            }
+            if !IntervalSet::covered_more_than(&group, 1).is_empty() {
+                acc_any += 1;
+            }
+            if !group_shared.is_empty() {
+                acc_shared += 1;
+            }
+
+            for &(start, end) in &group {
+                cleaned.insert(start, end);
+            }
+            assignments.extend(group);
+        }
+    }
+
+    if assignments.is_empty() {
+        println!("No assignments found");
+        return;
+    }
+
+    let span_start = assignments.iter().map(|r| r.0).min().unwrap();
+    let span_end = assignments.iter().map(|r| r.1).max().unwrap();
+    let idle = IntervalSet::from_ranges(&[(span_start, span_end)]).difference(&cleaned);
+    let crowded = IntervalSet::covered_more_than(&assignments, more_than);
+
+    println!("{} groups, sections {}-{}", group_count, span_start, span_end);
+    println!("{} groups where one member covers all others", acc_contained);
+    println!("{} groups with any overlap", acc_any);
+    println!("{} groups with a section shared by every member", acc_shared);
+    println!("{} sections nobody cleans: {}", idle.len(), format_ranges(&idle));
+    println!(
+        "{} sections cleaned by more than {} elves: {}",
+        crowded.len(),
+        more_than,
+        format_ranges(&crowded)
+    );
+}
+
+fn format_ranges(set: &IntervalSet) -> String {
+    set.ranges()
+        .iter()
+        .map(|(s, e)| if s == e { format!("{}", s) } else { format!("{}-{}", s, e) })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn line_to_ranges(line: &str) -> Vec<(u32, u32)> {
+    line.split(',')
+        .map(|slice| {
+            let (start, end) = slice.trim().split_once('-').unwrap();
+            (start.parse().unwrap(), end.parse().unwrap())
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod _tests {
-    use super::any_overlap;
+    use super::{any_overlap, line_to_ranges};
+    #[test]
+    fn test_line_to_ranges(){
+        assert_eq!(line_to_ranges("2-4,6-8"), vec![(2, 4), (6, 8)]);
+        assert_eq!(line_to_ranges("2-4, 6-8,1-9"), vec![(2, 4), (6, 8), (1, 9)]);
+    }
     #[test]
     fn any_overlap1(){
         assert!(any_overlap((0,10), (2,8)))
@@ -1,4 +1,4 @@
-use std::collections::VecDeque;
+use std::fmt;
 use std::path::PathBuf;
 
 use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
@@ -12,6 +12,122 @@ struct Operation {
     to: usize,
 }
 
+#[derive(PartialEq, Eq, Debug)]
+enum MoveError {
+    NoSuchStack { move_no: usize, stack: usize },
+    Underflow { move_no: usize, count: usize, from: usize, available: usize },
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MoveError::NoSuchStack { move_no, stack } => {
+                write!(f, "move {}: there is no stack {}", move_no, stack + 1)
+            }
+            MoveError::Underflow { move_no, count, from, available } => write!(
+                f,
+                "move {}: cannot take {} crates from stack {}, it holds only {}",
+                move_no,
+                count,
+                from + 1,
+                available
+            ),
+        }
+    }
+}
+
+/// Stack of crates, bottom crate first
+type Stack = Vec<char>;
+
+trait Crane {
+    /// Puts lifted crates (bottom crate first, as they stood) onto target stack
+    fn place(&self, batch: Stack, target: &mut Stack);
+
+    /// Whether lifts are flipped on the way, so putting crates back on the
+    /// stack they came from changes their order
+    fn reverses_batches(&self) -> bool {
+        false
+    }
+}
+
+struct CrateMover9000;
+
+impl Crane for CrateMover9000 {
+    fn place(&self, batch: Stack, target: &mut Stack) {
+        target.extend(batch.into_iter().rev());
+    }
+}
+
+struct CrateMover9001;
+
+impl Crane for CrateMover9001 {
+    fn place(&self, batch: Stack, target: &mut Stack) {
+        target.extend(batch);
+    }
+}
+
+/// Crane lifting at most `capacity` crates at once, optionally flipping
+/// every batch upside down on the way
+struct BatchCrane {
+    capacity: usize,
+    reversed: bool,
+}
+
+impl Crane for BatchCrane {
+    fn place(&self, batch: Stack, target: &mut Stack) {
+        target.reserve(batch.len());
+        for lift in batch.rchunks(self.capacity) {
+            if self.reversed {
+                target.extend(lift.iter().rev());
+            } else {
+                target.extend_from_slice(lift);
+            }
+        }
+    }
+
+    fn reverses_batches(&self) -> bool {
+        self.reversed
+    }
+}
+
+fn apply_move(crane: &dyn Crane, stacks: &mut [Stack], op: &Operation, move_no: usize) -> Result<(), MoveError> {
+    for stack in [op.from, op.to] {
+        if stack >= stacks.len() {
+            return Err(MoveError::NoSuchStack { move_no, stack });
+        }
+    }
+    let available = stacks[op.from].len();
+    if op.count > available {
+        return Err(MoveError::Underflow { move_no, count: op.count, from: op.from, available });
+    }
+    // crates put back where they were lifted from end up as they stood,
+    // unless the crane flips them on the way
+    if op.from == op.to && !crane.reverses_batches() {
+        return Ok(());
+    }
+    let batch = stacks[op.from].split_off(available - op.count);
+    crane.place(batch, &mut stacks[op.to]);
+    Ok(())
+}
+
+fn parse_crane(model: &str) -> Result<Box<dyn Crane>, String> {
+    let (name, capacity) = match model.split_once(':') {
+        Some((name, cap)) => match cap.parse::<usize>() {
+            Ok(cap) if cap > 0 => (name, Some(cap)),
+            _ => return Err(format!("invalid crane capacity '{}'", cap)),
+        },
+        None => (model, None),
+    };
+    match name {
+        "9000" | "9001" if capacity.is_some() => Err(format!("crane model '{}' takes no capacity", name)),
+        "9000" => Ok(Box::new(CrateMover9000)),
+        "9001" => Ok(Box::new(CrateMover9001)),
+        "lift" => Ok(Box::new(BatchCrane { capacity: capacity.unwrap_or(usize::MAX), reversed: false })),
+        "reverse" => Ok(Box::new(BatchCrane { capacity: capacity.unwrap_or(usize::MAX), reversed: true })),
+        _ => Err(format!("unknown crane model '{}'", model)),
+    }
+}
+
 pub fn cli() -> Command {
     Command::new("day05")
         .about("Elvish crate troubles")
@@ -19,6 +135,30 @@ pub fn cli() -> Command {
             Arg::new("modern")
                 .short('m')
                 .long("modern")
+                .help("Use CrateMover 9001, same as --crane 9001")
+                .conflicts_with("crane")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("crane")
+                .short('c')
+                .long("crane")
+                .help("Crane model: 9000, 9001, lift[:N] or reverse[:N]")
+                .value_parser(|s: &str| parse_crane(s).map(|_| s.to_string()))
+                .default_value("9000"),
+        )
+        .arg(
+            Arg::new("trace")
+                .short('t')
+                .long("trace")
+                .help("Print stack drawing after every move")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("draw")
+                .short('d')
+                .long("draw")
+                .help("Print final stacks as a drawing")
                 .action(ArgAction::SetTrue),
         )
         .arg(
@@ -30,51 +170,42 @@ pub fn cli() -> Command {
 
 pub fn handle(matches: &ArgMatches) {
     let path = matches.get_one::<std::path::PathBuf>("path");
-    let modern = matches.get_flag("modern");
-    solve(path.unwrap().to_path_buf(), modern);
+    let crane = if matches.get_flag("modern") {
+        Box::new(CrateMover9001)
+    } else {
+        parse_crane(matches.get_one::<String>("crane").unwrap()).unwrap()
+    };
+    let trace = matches.get_flag("trace");
+    let draw = matches.get_flag("draw");
+    solve(path.unwrap().to_path_buf(), crane.as_ref(), trace, draw);
 }
 
-fn solve(filepath: PathBuf, modern: bool) {
-    let mut crate_stacks: Vec<VecDeque<char>> = Vec::new();
-    let mut column_count = 0;
+fn solve(filepath: PathBuf, crane: &dyn Crane, trace: bool, draw: bool) {
+    let mut crate_stacks: Vec<Stack> = Vec::new();
+    let mut drawing: Vec<String> = Vec::new();
     let mut analyzing_state = true;
-    let mut crate_buffer: VecDeque<char> = VecDeque::new();
+    let mut move_no = 0;
 
     if let Ok(lines) = common::read_lines(filepath) {
         for line in lines {
             if let Ok(line_text) = line {
                 if analyzing_state {
-                    if column_count == 0 {
-                        column_count = (line_text.len() + 1) / 4;
-                        for _ in 0..column_count {
-                            crate_stacks.push(VecDeque::new());
-                        }
-                    }
                     if line_text.is_empty() {
                         analyzing_state = false;
+                        crate_stacks = parse_drawing(&drawing);
                     } else {
-                        if let Some(crate_vec) = crate_line_split(&line_text) {
-                            for (i, co) in crate_vec.iter().enumerate() {
-                                if let Some(c) = co {
-                                    crate_stacks[i].push_front(*c);
-                                }
-                            }
-                        }
+                        drawing.push(line_text);
                     }
                 } else {
                     let op = parse_operation(&line_text);
-                    if modern {
-                        for _ in 0..op.count {
-                            crate_buffer.push_front(crate_stacks[op.from].pop_back().unwrap());
-                        }
-                        for _ in 0..op.count {
-                            crate_stacks[op.to].push_back(crate_buffer.pop_front().unwrap());
-                        }
-                    } else {
-                        for _ in 0..op.count {
-                            let buffer = crate_stacks[op.from].pop_back().unwrap();
-                            crate_stacks[op.to].push_back(buffer);
-                        }
+                    move_no += 1;
+                    if let Err(err) = apply_move(crane, &mut crate_stacks, &op, move_no) {
+                        eprintln!("{}", err);
+                        std::process::exit(1);
+                    }
+                    if trace {
+                        println!("{}", line_text);
+                        println!("{}", render_stacks(&crate_stacks));
                     }
                 }
             }
@@ -82,6 +213,10 @@ fn solve(filepath: PathBuf, modern: bool) {
     }
 
     println!("Final result:");
+    if draw {
+        println!("{}", render_stacks(&crate_stacks));
+        return;
+    }
     for (i, vc) in crate_stacks.iter().enumerate() {
         print!("{} |", i);
         for element in vc {
@@ -91,6 +226,42 @@ fn solve(filepath: PathBuf, modern: bool) {
     }
 }
 
+/// Reads stacks from the puzzle drawing, number row included
+fn parse_drawing<S: AsRef<str>>(lines: &[S]) -> Vec<Stack> {
+    let column_count = lines.iter().map(|l| (l.as_ref().len() + 1) / 4).max().unwrap_or(0);
+    let mut crate_stacks: Vec<Stack> = vec![Stack::new(); column_count];
+    // drawing is read top-down, stacks are kept bottom-up
+    for line in lines.iter().rev() {
+        if let Some(crate_vec) = crate_line_split(line.as_ref()) {
+            for (i, co) in crate_vec.iter().enumerate() {
+                if let Some(c) = co {
+                    crate_stacks[i].push(*c);
+                }
+            }
+        }
+    }
+    crate_stacks
+}
+
+/// Draws stacks the way puzzle input does, so parse_drawing can read it back
+fn render_stacks(stacks: &[Stack]) -> String {
+    let height = stacks.iter().map(|s| s.len()).max().unwrap_or(0);
+    let mut lines: Vec<String> = Vec::new();
+    for level in (0..height).rev() {
+        let cells: Vec<String> = stacks
+            .iter()
+            .map(|s| match s.get(level) {
+                Some(c) => format!("[{}]", c),
+                None => String::from("   "),
+            })
+            .collect();
+        lines.push(cells.join(" "));
+    }
+    let numbers: Vec<String> = (1..=stacks.len()).map(|i| format!("{:^3}", i)).collect();
+    lines.push(numbers.join(" "));
+    lines.join("\n")
+}
+
 fn parse_operation(line: &str) -> Operation {
     let vec: Vec<&str> = line.split(' ').collect();
     Operation {
@@ -121,7 +292,107 @@ fn crate_line_split(line: &str) -> Option<Vec<Option<char>>> {
 
 #[cfg(test)]
 mod _tests {
-    use super::{crate_line_split, parse_operation, Operation};
+    use super::{
+        apply_move, crate_line_split, parse_crane, parse_drawing, parse_operation, render_stacks, MoveError,
+        Operation, Stack,
+    };
+
+    fn sample_stacks() -> Vec<Stack> {
+        vec![vec!['Z', 'N'], vec!['M', 'C', 'D'], vec!['P']]
+    }
+
+
+    #[test]
+    fn test_crane_models() {
+        let op = Operation { count: 3, from: 1, to: 2 };
+        let expected = [
+            ("9000", "PDCM"),
+            ("9001", "PMCD"),
+            ("lift:2", "PCDM"),
+            ("lift", "PMCD"),
+            ("reverse", "PDCM"),
+            ("reverse:2", "PDCM"),
+            ("reverse:1", "PDCM"),
+        ];
+        for (model, stack) in expected {
+            let mut stacks = sample_stacks();
+            apply_move(parse_crane(model).unwrap().as_ref(), &mut stacks, &op, 1).unwrap();
+            assert!(stacks[1].is_empty());
+            assert_eq!(stacks[2].iter().collect::<String>(), stack, "model {}", model);
+        }
+        assert!(parse_crane("9002").is_err());
+        assert!(parse_crane("lift:0").is_err());
+        assert!(parse_crane("9000:3").is_err());
+        assert!(parse_crane("9001:3").is_err());
+    }
+    #[test]
+    fn test_apply_move_errors() {
+        let crane = parse_crane("9000").unwrap();
+        let mut stacks = sample_stacks();
+        assert_eq!(
+            apply_move(crane.as_ref(), &mut stacks, &Operation { count: 3, from: 0, to: 1 }, 7),
+            Err(MoveError::Underflow { move_no: 7, count: 3, from: 0, available: 2 })
+        );
+        assert_eq!(
+            apply_move(crane.as_ref(), &mut stacks, &Operation { count: 1, from: 0, to: 3 }, 8),
+            Err(MoveError::NoSuchStack { move_no: 8, stack: 3 })
+        );
+        assert_eq!(stacks, sample_stacks());
+        apply_move(crane.as_ref(), &mut stacks, &Operation { count: 1, from: 0, to: 0 }, 9).unwrap();
+        assert_eq!(stacks, sample_stacks());
+        for model in ["9000", "9001", "lift:2"] {
+            let crane = parse_crane(model).unwrap();
+            apply_move(crane.as_ref(), &mut stacks, &Operation { count: 3, from: 1, to: 1 }, 10).unwrap();
+            assert_eq!(stacks, sample_stacks(), "model {}", model);
+        }
+        let crane = parse_crane("reverse").unwrap();
+        apply_move(crane.as_ref(), &mut stacks, &Operation { count: 3, from: 1, to: 1 }, 11).unwrap();
+        assert_eq!(stacks, vec![vec!['Z', 'N'], vec!['D', 'C', 'M'], vec!['P']]);
+    }
+    fn assert_round_trip(drawing: &str) {
+        let lines: Vec<&str> = drawing.split('\n').collect();
+        assert_eq!(render_stacks(&parse_drawing(&lines)), drawing);
+    }
+    #[test]
+    fn test_drawing_round_trip_sample() {
+        assert_round_trip("    [D]    \n[N] [C]    \n[Z] [M] [P]\n 1   2   3 ");
+    }
+    #[test]
+    fn test_drawing_round_trip_full_width() {
+        assert_round_trip(
+            "[T]     [D]         [L]            \n\
+             [R]     [S] [G]     [P]         [H]\n\
+             [G]     [J] [W]     [Q]     [M] [V]\n\
+             [V] [Q] [H] [N] [F] [R]     [W] [D]\n\
+             [Z] [B] [F] [L] [M] [W] [Z] [N] [Q]\n \
+             1   2   3   4   5   6   7   8   9 ",
+        );
+    }
+    #[test]
+    fn test_drawing_round_trip_empty_stacks() {
+        assert_round_trip(" 1   2   3 ");
+        assert_round_trip("        [A]\n 1   2   3 ");
+        assert_round_trip("[A]        \n 1   2   3 ");
+    }
+    #[test]
+    fn test_drawing_round_trip_after_moves() {
+        let mut stacks = sample_stacks();
+        let crane = parse_crane("9001").unwrap();
+        apply_move(crane.as_ref(), &mut stacks, &Operation { count: 2, from: 1, to: 0 }, 1).unwrap();
+        apply_move(crane.as_ref(), &mut stacks, &Operation { count: 1, from: 2, to: 1 }, 2).unwrap();
+        let drawing = render_stacks(&stacks);
+        assert_eq!(drawing, "[D]        \n[C]        \n[N] [P]    \n[Z] [M]    \n 1   2   3 ");
+        assert_round_trip(&drawing);
+        let lines: Vec<&str> = drawing.split('\n').collect();
+        assert_eq!(parse_drawing(&lines), stacks);
+    }
+    #[test]
+    fn test_render_stacks() {
+        assert_eq!(
+            render_stacks(&sample_stacks()),
+            "    [D]    \n[N] [C]    \n[Z] [M] [P]\n 1   2   3 "
+        );
+    }
     #[test]
     fn test_parse_operation() {
         assert_eq!(
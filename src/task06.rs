@@ -1,88 +1,379 @@
-use std::{path::PathBuf, collections::VecDeque};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
 
-use clap::{value_parser, ArgMatches, Command};
+use clap::builder::RangedU64ValueParser;
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
 
-use crate::common;
+const CHUNK_SIZE: usize = 64 * 1024;
 
 pub fn cli() -> Command {
-    Command::new("day06").about("Elvish signal standards").arg(
-        clap::arg!(path: <PATH>)
-            .required(true)
-            .value_parser(value_parser!(std::path::PathBuf)),
-    )
+    Command::new("day06")
+        .about("Elvish signal standards")
+        .arg(
+            Arg::new("window")
+                .short('w')
+                .long("window")
+                .help("Marker window sizes to look for")
+                .value_parser(RangedU64ValueParser::<usize>::new().range(1..))
+                .value_delimiter(',')
+                .action(ArgAction::Append)
+                .default_values(["4", "14"]),
+        )
+        .arg(
+            Arg::new("frames")
+                .short('f')
+                .long("frames")
+                .help("Split signals into frames at successive markers, cycling through window sizes, and print them as JSON")
+                .conflicts_with("all")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("all")
+                .short('a')
+                .long("all")
+                .help("Report every marker position, not only the first one")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            clap::arg!(path: <PATH>)
+                .help("Signal file, - reads stdin")
+                .required(true)
+                .value_parser(value_parser!(std::path::PathBuf)),
+        )
 }
 
 pub fn handle(matches: &ArgMatches) {
     let path = matches.get_one::<std::path::PathBuf>("path");
-    solve(path.unwrap().to_path_buf());
+    let sizes: Vec<usize> = matches.get_many::<usize>("window").unwrap().copied().collect();
+    let all = matches.get_flag("all");
+    if matches.get_flag("frames") {
+        solve_frames(path.unwrap().to_path_buf(), &sizes);
+    } else {
+        solve(path.unwrap().to_path_buf(), &sizes, all);
+    }
 }
 
-fn solve(filepath: PathBuf) {
+fn solve_frames(filepath: PathBuf, sizes: &[usize]) {
+    let mut input = Vec::new();
+    let read = if filepath.as_os_str() == "-" {
+        io::stdin().lock().read_to_end(&mut input)
+    } else {
+        File::open(&filepath).and_then(|mut file| file.read_to_end(&mut input))
+    };
+    if let Err(err) = read {
+        eprintln!("Cannot read {}: {}", filepath.display(), err);
+        return;
+    }
+
+    let signals: Vec<serde_json::Value> = input
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(|line| !line.is_empty())
+        .map(|signal| frames_to_json(signal, &decode_frames(signal, sizes)))
+        .collect();
+    println!("{}", serde_json::to_string_pretty(&signals).unwrap());
+}
 
-    if let Ok(lines) = common::read_lines(filepath) {
-        for line in lines {
-            if let Ok(line_text) = line {
-                println!("{} begins sequence", find_diff_sequence(&line_text, 4));
-                println!("{} begins message", find_diff_sequence(&line_text, 14));
+fn solve(filepath: PathBuf, sizes: &[usize], all: bool) {
+    let mut found = Vec::new();
+    let mut on_event = |event| match event {
+        ScanEvent::Marker(size, pos) => {
+            found.push(size);
+            match (all, size) {
+                (true, _) => println!("window {}: marker at {}", size, pos),
+                (false, 4) => println!("{} begins sequence", pos),
+                (false, 14) => println!("{} begins message", pos),
+                (false, _) => println!("{} begins window {}", pos, size),
             }
         }
+        ScanEvent::EndOfSignal => {
+            for size in sizes.iter().filter(|size| !found.contains(size)) {
+                println!("no marker for window {}", size);
+            }
+            found.clear();
+        }
+    };
+    let scanned = if filepath.as_os_str() == "-" {
+        scan_signals(io::stdin().lock(), sizes, all, &mut on_event)
+    } else {
+        File::open(&filepath).and_then(|file| scan_signals(file, sizes, all, &mut on_event))
+    };
+    if let Err(err) = scanned {
+        eprintln!("Cannot read {}: {}", filepath.display(), err);
     }
-    
 }
 
-fn find_diff_sequence(signal: &str, buf_size: usize) -> usize {
-    let mut buffer = VecDeque::new();
-    let mut chars = signal.chars();
-    for pos in 0..signal.len() {
-        if buffer.len() < buf_size {
-            buffer.push_back(chars.next().unwrap())
+/// Tracks how many distinct bytes repeat inside a sliding window
+struct WindowTracker {
+    size: usize,
+    counts: [u32; 256],
+    repeated: usize,
+    /// Set once the first marker is reported when only that one is wanted
+    done: bool,
+}
+
+impl WindowTracker {
+    fn new(size: usize) -> WindowTracker {
+        WindowTracker { size, counts: [0; 256], repeated: 0, done: false }
+    }
+
+    fn push(&mut self, incoming: u8, outgoing: Option<u8>) {
+        if let Some(out) = outgoing {
+            self.counts[out as usize] -= 1;
+            if self.counts[out as usize] == 1 {
+                self.repeated -= 1;
+            }
+        }
+        self.counts[incoming as usize] += 1;
+        if self.counts[incoming as usize] == 2 {
+            self.repeated += 1;
         }
-        else {
-            if is_unique(&buffer) {
-                return pos;
+    }
+}
+
+/// Finds unique-byte windows of several sizes in a single pass over a stream
+struct MarkerDetector {
+    trackers: Vec<WindowTracker>,
+    history: Vec<u8>,
+    processed: usize,
+    /// Report every marker instead of only the first one per window size
+    all: bool,
+}
+
+impl MarkerDetector {
+    fn new(sizes: &[usize], all: bool) -> MarkerDetector {
+        let history_len = sizes.iter().copied().max().unwrap_or(0);
+        MarkerDetector {
+            trackers: sizes.iter().map(|&s| WindowTracker::new(s)).collect(),
+            history: vec![0; history_len],
+            processed: 0,
+            all,
+        }
+    }
+
+    /// No window size is left to look for in the current signal
+    fn finished(&self) -> bool {
+        self.trackers.iter().all(|tracker| tracker.done)
+    }
+
+    fn reset(&mut self) {
+        for tracker in self.trackers.iter_mut() {
+            *tracker = WindowTracker::new(tracker.size);
+        }
+        self.processed = 0;
+    }
+
+    /// Feeds one byte, calling `on_marker(window size, position)` for every
+    /// window that holds only unique bytes after it
+    fn push<F: FnMut(usize, usize)>(&mut self, byte: u8, mut on_marker: F) {
+        let history_len = self.history.len();
+        for tracker in self.trackers.iter_mut().filter(|tracker| !tracker.done) {
+            let outgoing = if self.processed >= tracker.size {
+                Some(self.history[(self.processed - tracker.size) % history_len])
+            } else {
+                None
+            };
+            tracker.push(byte, outgoing);
+        }
+        self.history[self.processed % history_len] = byte;
+        self.processed += 1;
+        for tracker in self.trackers.iter_mut().filter(|tracker| !tracker.done) {
+            if self.processed >= tracker.size && tracker.repeated == 0 {
+                on_marker(tracker.size, self.processed);
+                tracker.done = !self.all;
             }
-            else {
-                buffer.pop_front();
-                buffer.push_back(chars.next().unwrap());
+        }
+    }
+}
+
+enum ScanEvent {
+    /// Window size and position right after the marker
+    Marker(usize, usize),
+    EndOfSignal,
+}
+
+/// Reads newline separated signals, reporting markers as they are found.
+/// Without `all` only the first marker of every window size is reported and
+/// the rest of a signal is skipped once each size has one.
+fn scan_signals<R: Read, F: FnMut(ScanEvent)>(mut reader: R, sizes: &[usize], all: bool, mut on_event: F) -> io::Result<()> {
+    let mut detector = MarkerDetector::new(sizes, all);
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        for &byte in &buffer[..read] {
+            match byte {
+                b'\r' => {}
+                b'\n' => {
+                    if detector.processed > 0 {
+                        on_event(ScanEvent::EndOfSignal);
+                    }
+                    detector.reset();
+                }
+                _ if detector.finished() => detector.processed += 1,
+                _ => detector.push(byte, |size, pos| on_event(ScanEvent::Marker(size, pos))),
             }
         }
     }
-    return 0;
+    if detector.processed > 0 {
+        on_event(ScanEvent::EndOfSignal);
+    }
+    Ok(())
+}
+
+#[derive(PartialEq, Eq, Debug)]
+struct Frame {
+    window: usize,
+    marker_offset: usize,
+    payload_offset: usize,
+    payload_len: usize,
 }
 
-fn is_unique(coll: &VecDeque<char>) -> bool {
-    for pos in 1..coll.len(){
-        for pos2 in 0..pos{
-            if coll[pos] == coll[pos2] {
-                return false;
+/// Cuts a signal into frames. Every frame opens with a marker of the next
+/// window size (cycling through `sizes`) searched for right after the
+/// previous marker, and its payload lasts until the following marker.
+fn decode_frames(signal: &[u8], sizes: &[usize]) -> Vec<Frame> {
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut start = 0;
+    while start < signal.len() {
+        let window = sizes[frames.len() % sizes.len()];
+        let mut detector = MarkerDetector::new(&[window], false);
+        let mut found = None;
+        for &byte in &signal[start..] {
+            detector.push(byte, |_, pos| {
+                found.get_or_insert(start + pos);
+            });
+            if found.is_some() {
+                break;
             }
         }
+        let Some(payload_offset) = found else {
+            break;
+        };
+        let marker_offset = payload_offset - window;
+        if let Some(previous) = frames.last_mut() {
+            previous.payload_len = marker_offset - previous.payload_offset;
+        }
+        frames.push(Frame { window, marker_offset, payload_offset, payload_len: 0 });
+        start = payload_offset;
+    }
+    if let Some(last) = frames.last_mut() {
+        last.payload_len = signal.len() - last.payload_offset;
     }
-    return true;
+    frames
+}
+
+fn frames_to_json(signal: &[u8], frames: &[Frame]) -> serde_json::Value {
+    let preamble_len = frames.first().map_or(signal.len(), |f| f.marker_offset);
+    let frames: Vec<serde_json::Value> = frames
+        .iter()
+        .enumerate()
+        .map(|(index, frame)| {
+            let payload_end = frame.payload_offset + frame.payload_len;
+            serde_json::json!({
+                "index": index,
+                "window": frame.window,
+                "marker_offset": frame.marker_offset,
+                "marker": String::from_utf8_lossy(&signal[frame.marker_offset..frame.payload_offset]),
+                "payload_offset": frame.payload_offset,
+                "payload": String::from_utf8_lossy(&signal[frame.payload_offset..payload_end]),
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "length": signal.len(),
+        "preamble": String::from_utf8_lossy(&signal[..preamble_len]),
+        "frames": frames,
+    })
 }
 
 
 #[cfg(test)]
 mod _tests {
-    use std::collections::VecDeque;
+    use super::{decode_frames, frames_to_json, scan_signals, Frame, ScanEvent};
+
+    /// Markers of every signal, (window size, position)
+    fn scan(input: &str, sizes: &[usize], all: bool) -> Vec<Vec<(usize, usize)>> {
+        let mut signals = vec![Vec::new()];
+        scan_signals(input.as_bytes(), sizes, all, |event| match event {
+            ScanEvent::Marker(size, pos) => signals.last_mut().unwrap().push((size, pos)),
+            ScanEvent::EndOfSignal => signals.push(Vec::new()),
+        })
+        .unwrap();
+        signals.pop();
+        signals
+    }
 
-    use super::{find_diff_sequence, is_unique};
+    fn find_diff_sequence(signal: &str, buf_size: usize) -> Option<usize> {
+        scan(signal, &[buf_size], false)[0].first().map(|m| m.1)
+    }
     #[test]
     fn test_find_diff_sequence(){
-        assert_eq!(find_diff_sequence(&String::from("bvwbjplbgvbhsrlpgdmjqwftvncz"), 4), 5);
-        assert_eq!(find_diff_sequence(&String::from("nppdvjthqldpwncqszvftbrmjlhg"), 4), 6);
-        assert_eq!(find_diff_sequence(&String::from("nznrnfrfntjfmvfwmzdfjlvtqnbhcprsg"), 4), 10);
-        assert_eq!(find_diff_sequence(&String::from("zcfzfwzzqfrljwzlrfnpqdbhtmscgvjw"), 4), 11);
-        assert_eq!(find_diff_sequence(&String::from("mjqjpqmgbljsphdztnvjfqwrcgsmlb"), 14), 19);
-        assert_eq!(find_diff_sequence(&String::from("bvwbjplbgvbhsrlpgdmjqwftvncz"), 14), 23);
-        assert_eq!(find_diff_sequence(&String::from("nppdvjthqldpwncqszvftbrmjlhg"), 14), 23);
-        assert_eq!(find_diff_sequence(&String::from("nznrnfrfntjfmvfwmzdfjlvtqnbhcprsg"), 14), 29);
-        assert_eq!(find_diff_sequence(&String::from("zcfzfwzzqfrljwzlrfnpqdbhtmscgvjw"), 14), 26);
+        assert_eq!(find_diff_sequence(&String::from("bvwbjplbgvbhsrlpgdmjqwftvncz"), 4), Some(5));
+        assert_eq!(find_diff_sequence(&String::from("nppdvjthqldpwncqszvftbrmjlhg"), 4), Some(6));
+        assert_eq!(find_diff_sequence(&String::from("nznrnfrfntjfmvfwmzdfjlvtqnbhcprsg"), 4), Some(10));
+        assert_eq!(find_diff_sequence(&String::from("zcfzfwzzqfrljwzlrfnpqdbhtmscgvjw"), 4), Some(11));
+        assert_eq!(find_diff_sequence(&String::from("mjqjpqmgbljsphdztnvjfqwrcgsmlb"), 14), Some(19));
+        assert_eq!(find_diff_sequence(&String::from("bvwbjplbgvbhsrlpgdmjqwftvncz"), 14), Some(23));
+        assert_eq!(find_diff_sequence(&String::from("nppdvjthqldpwncqszvftbrmjlhg"), 14), Some(23));
+        assert_eq!(find_diff_sequence(&String::from("nznrnfrfntjfmvfwmzdfjlvtqnbhcprsg"), 14), Some(29));
+        assert_eq!(find_diff_sequence(&String::from("zcfzfwzzqfrljwzlrfnpqdbhtmscgvjw"), 14), Some(26));
+    }
+    #[test]
+    fn test_find_diff_sequence_missing(){
+        assert_eq!(find_diff_sequence("aaaaaaaa", 2), None);
+        assert_eq!(find_diff_sequence("abc", 4), None);
+        assert_eq!(find_diff_sequence("abcd", 4), Some(4));
+    }
+    #[test]
+    fn test_scan_signals(){
+        let input = "abcabd\naab\n\n";
+        assert_eq!(
+            scan(input, &[3, 4], true),
+            vec![
+                vec![(3, 3), (3, 4), (3, 5), (3, 6), (4, 6)],
+                vec![],
+            ]
+        );
+        assert_eq!(scan(input, &[3, 4], false), vec![vec![(3, 3), (4, 6)], vec![]]);
+        // trackers restart for every signal
+        assert_eq!(scan("abcd\nxyzzy\nqrst", &[4], false), vec![vec![(4, 4)], vec![], vec![(4, 4)]]);
     }
     #[test]
-    fn test_is_unique(){
-        assert!(is_unique(&VecDeque::from(vec!['a','b','c','d'])));
-        assert!(!is_unique(&VecDeque::from(vec!['a','b','c','a'])));
+    fn test_decode_frames(){
+        let signal = b"aabcdxxyzwqqq";
+        let frames = decode_frames(signal, &[3]);
+        assert_eq!(
+            frames,
+            vec![
+                Frame { window: 3, marker_offset: 1, payload_offset: 4, payload_len: 2 },
+                Frame { window: 3, marker_offset: 6, payload_offset: 9, payload_len: 4 },
+            ]
+        );
+        let json = frames_to_json(signal, &frames);
+        assert_eq!(json["preamble"], "a");
+        assert_eq!(json["frames"][0]["marker"], "abc");
+        assert_eq!(json["frames"][0]["payload"], "dx");
+        assert_eq!(json["frames"][1]["marker"], "xyz");
+        assert_eq!(json["frames"][1]["payload_offset"], 9);
+        assert_eq!(json["frames"][1]["payload"], "wqqq");
+    }
+    #[test]
+    fn test_decode_frames_cycles_windows(){
+        let frames = decode_frames(b"abxabcdzz", &[2, 4]);
+        assert_eq!(
+            frames,
+            vec![
+                Frame { window: 2, marker_offset: 0, payload_offset: 2, payload_len: 0 },
+                Frame { window: 4, marker_offset: 2, payload_offset: 6, payload_len: 0 },
+                Frame { window: 2, marker_offset: 6, payload_offset: 8, payload_len: 1 },
+            ]
+        );
+        assert_eq!(decode_frames(b"aaaa", &[2]), vec![]);
     }
 }
-
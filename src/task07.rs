@@ -1,12 +1,16 @@
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
 
-use clap::{value_parser, ArgMatches, Command};
+use clap::{value_parser, Arg, ArgAction, ArgGroup, ArgMatches, Command};
 
 use crate::common;
 
+const TAR_BLOCK: usize = 512;
+
 enum FileTree {
-    Dir(HashMap<String, FileTree>),
+    Dir(BTreeMap<String, FileTree>),
     File(u64),
 }
 
@@ -16,72 +20,359 @@ enum InputOutput {
     CdUp,
     CdDir(String),
     Ls,
+    Pwd,
+    Mkdir(String),
+    Rm(String),
     DirInfo(String),
     FileInfo(String, u64),
+    PathInfo(String),
     Unknown,
 }
 
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum SizeFilter {
+    Above(u64),
+    Below(u64),
+    Exactly(u64),
+}
+
+impl SizeFilter {
+    fn matches(&self, size: u64) -> bool {
+        match *self {
+            SizeFilter::Above(limit) => size > limit,
+            SizeFilter::Below(limit) => size < limit,
+            SizeFilter::Exactly(limit) => size == limit,
+        }
+    }
+}
+
+/// Directory that may be deleted, its descendants take the preorder
+/// indices from `start` up to `end`
+struct Candidate {
+    path: Vec<String>,
+    size: u64,
+    start: usize,
+    end: usize,
+}
+
+impl Candidate {
+    /// One of the two directories lies inside the other
+    fn overlaps(&self, other: &Candidate) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+}
+
+/// Upper limit on search steps when planning a cleanup, a search that has
+/// not proven its best plan minimal by then gives up
+const PLAN_SEARCH_STEPS: usize = 1_000_000;
+
+/// Largest amount to reclaim that the lower bound is worked out for, one bit
+/// of memory per byte
+const LOWER_BOUND_LIMIT: u64 = 1 << 28;
+
+/// Branch and bound over candidates sorted by size, largest first
+struct CleanupSearch<'a> {
+    candidates: &'a [Candidate],
+    /// Size of all candidates from an index on, nested ones counted twice
+    remaining: Vec<u64>,
+    needed: u64,
+    taken: Vec<usize>,
+    best: Option<(u64, Vec<usize>)>,
+    /// No plan frees less, a plan freeing this much ends the search
+    lower: u64,
+    steps: usize,
+}
+
+struct Entry {
+    path: String,
+    is_dir: bool,
+    size: u64,
+}
+
+/// Filesystem rebuilt from a terminal session
+struct Vfs {
+    root: FileTree,
+    cwd: Vec<String>,
+    listing: Option<BTreeMap<String, FileTree>>,
+}
+
+impl Vfs {
+    fn new() -> Vfs {
+        Vfs {
+            root: FileTree::Dir(BTreeMap::new()),
+            cwd: Vec::new(),
+            listing: None,
+        }
+    }
+
+    /// Turns absolute or cwd-relative path into list of components,
+    /// `..` at root stays at root like a real shell would
+    fn resolve(&self, path: &str) -> Vec<String> {
+        let mut resolved = if path.starts_with('/') {
+            Vec::new()
+        } else {
+            self.cwd.clone()
+        };
+        for part in path.split('/') {
+            match part {
+                "" | "." => {}
+                ".." => {
+                    resolved.pop();
+                }
+                name => resolved.push(name.to_string()),
+            }
+        }
+        resolved
+    }
+
+    fn get(&self, path: &[String]) -> Option<&FileTree> {
+        let mut node = &self.root;
+        for name in path {
+            match node {
+                FileTree::Dir(children) => node = children.get(name)?,
+                FileTree::File(_) => return None,
+            }
+        }
+        Some(node)
+    }
+
+    /// Directory at path, created along the way if the session never listed it.
+    /// Files standing in the way are replaced, the latest session output wins.
+    fn dir_mut(&mut self, path: &[String]) -> &mut BTreeMap<String, FileTree> {
+        let mut node = &mut self.root;
+        for name in path {
+            node = match node {
+                FileTree::Dir(children) => children
+                    .entry(name.clone())
+                    .or_insert_with(|| FileTree::Dir(BTreeMap::new())),
+                FileTree::File(_) => unreachable!(),
+            };
+            if let FileTree::File(_) = node {
+                *node = FileTree::Dir(BTreeMap::new());
+            }
+        }
+        match node {
+            FileTree::Dir(children) => children,
+            FileTree::File(_) => unreachable!(),
+        }
+    }
+
+    fn add_entry(&mut self, name: &str, to_add: FileTree) {
+        if let Some(listing) = self.listing.as_mut() {
+            listing.insert(name.to_string(), to_add);
+            return;
+        }
+        let cwd = self.cwd.clone();
+        let children = self.dir_mut(&cwd);
+        match (children.get(name), &to_add) {
+            (Some(FileTree::Dir(_)), FileTree::Dir(_)) => {}
+            _ => {
+                children.insert(name.to_string(), to_add);
+            }
+        }
+    }
+
+    /// Replaces contents of cwd with the latest `ls` output, keeping
+    /// already known subdirectories that are still listed
+    fn finish_listing(&mut self) {
+        let Some(listing) = self.listing.take() else {
+            return;
+        };
+        let cwd = self.cwd.clone();
+        let children = self.dir_mut(&cwd);
+        let mut previous = std::mem::take(children);
+        for (name, entry) in listing {
+            let merged = match (previous.remove(&name), entry) {
+                (Some(known @ FileTree::Dir(_)), FileTree::Dir(_)) => known,
+                (_, entry) => entry,
+            };
+            children.insert(name, merged);
+        }
+    }
+
+    fn remove(&mut self, path: &[String]) -> Result<(), String> {
+        let Some((name, parent)) = path.split_last() else {
+            return Err(String::from("cannot remove root directory"));
+        };
+        match self.get(parent) {
+            Some(FileTree::Dir(children)) if children.contains_key(name) => {}
+            _ => return Err(format!("no such file or directory {}", format_path(path))),
+        }
+        self.dir_mut(parent).remove(name);
+        Ok(())
+    }
+
+    fn apply(&mut self, io: InputOutput) -> Result<(), String> {
+        match io {
+            InputOutput::DirInfo(_) | InputOutput::FileInfo(_, _) | InputOutput::PathInfo(_) => {}
+            _ => self.finish_listing(),
+        }
+        match io {
+            InputOutput::CdRoot => self.cwd.clear(),
+            InputOutput::CdUp => {
+                self.cwd.pop();
+            }
+            InputOutput::CdDir(dirname) => {
+                let path = self.resolve(&dirname);
+                self.dir_mut(&path);
+                self.cwd = path;
+            }
+            InputOutput::Ls => self.listing = Some(BTreeMap::new()),
+            InputOutput::Mkdir(dirname) => {
+                let path = self.resolve(&dirname);
+                self.dir_mut(&path);
+            }
+            InputOutput::Rm(name) => {
+                let path = self.resolve(&name);
+                self.remove(&path)?;
+            }
+            InputOutput::DirInfo(dirname) => self.add_entry(&dirname, FileTree::Dir(BTreeMap::new())),
+            InputOutput::FileInfo(filename, size) => self.add_entry(&filename, FileTree::File(size)),
+            InputOutput::Pwd | InputOutput::PathInfo(_) => {
+                //nothing happens
+            }
+            InputOutput::Unknown => return Err(String::from("unsupported operation")),
+        }
+        Ok(())
+    }
+
+    /// Every element below path with its total size, children before parents
+    fn entries(&self, path: &[String]) -> Vec<Entry> {
+        let mut acc = Vec::new();
+        if let Some(node) = self.get(path) {
+            collect_entries(node, &mut path.to_vec(), &mut acc);
+        }
+        acc
+    }
+}
+
 pub fn cli() -> Command {
+    let dir_arg = || Arg::new("dir").help("Directory to start from").default_value("/");
+    let path_arg = || {
+        clap::arg!(path: <PATH>)
+            .required(true)
+            .value_parser(value_parser!(std::path::PathBuf))
+    };
     Command::new("day07")
         .about("Elvish inability to use du command")
-        .arg(
-            clap::arg!(path: <PATH>)
-                .required(true)
-                .value_parser(value_parser!(std::path::PathBuf)),
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .arg(path_arg())
+        .subcommand(
+            Command::new("query")
+                .about("Query filesystem rebuilt from terminal session")
+                .subcommand_required(true)
+                .arg(path_arg())
+                .subcommand(Command::new("du").about("Size of every directory").arg(dir_arg()))
+                .subcommand(
+                    Command::new("find")
+                        .about("Files and directories matching filters")
+                        .arg(dir_arg())
+                        .arg(
+                            Arg::new("size")
+                                .long("size")
+                                .help("Size filter, +N above, -N below, N exactly")
+                                .allow_hyphen_values(true)
+                                .value_parser(parse_size_filter),
+                        )
+                        .arg(
+                            Arg::new("type")
+                                .long("type")
+                                .help("f for files, d for directories")
+                                .value_parser(["f", "d"]),
+                        ),
+                )
+                .subcommand(Command::new("tree").about("Directory tree").arg(dir_arg()))
+                .subcommand(
+                    Command::new("largest")
+                        .about("Largest directories")
+                        .arg(
+                            Arg::new("count")
+                                .value_parser(value_parser!(usize))
+                                .default_value("10"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Materialise filesystem rebuilt from terminal session")
+                .arg(path_arg())
+                .arg(
+                    Arg::new("dir")
+                        .long("dir")
+                        .help("Empty or missing directory to create sparse files in")
+                        .value_parser(value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    Arg::new("tar")
+                        .long("tar")
+                        .help("Tar archive to write, - for stdout")
+                        .value_parser(value_parser!(std::path::PathBuf)),
+                )
+                .group(ArgGroup::new("target").args(["dir", "tar"]).required(true)),
+        )
+        .subcommand(
+            Command::new("plan")
+                .about("Smallest set of directories to delete for the update")
+                .arg(path_arg())
+                .arg(
+                    Arg::new("disk")
+                        .long("disk")
+                        .help("Total disk space")
+                        .value_parser(value_parser!(u64))
+                        .default_value("70000000"),
+                )
+                .arg(
+                    Arg::new("free")
+                        .long("free")
+                        .help("Free space required")
+                        .value_parser(value_parser!(u64))
+                        .default_value("30000000"),
+                )
+                .arg(
+                    Arg::new("protect")
+                        .long("protect")
+                        .help("Path that must survive the cleanup, may be repeated")
+                        .action(ArgAction::Append),
+                ),
         )
 }
 
 pub fn handle(matches: &ArgMatches) {
-    let path = matches.get_one::<std::path::PathBuf>("path");
-    solve(path.unwrap().to_path_buf());
+    let sub_matches = matches.subcommand().map_or(matches, |(_, sub_matches)| sub_matches);
+    let path = sub_matches.get_one::<std::path::PathBuf>("path");
+    let vfs = match build_vfs(path.unwrap()) {
+        Ok(vfs) => vfs,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    match matches.subcommand() {
+        Some(("query", sub_matches)) => query(&vfs, sub_matches),
+        Some(("export", sub_matches)) => export(&vfs, sub_matches),
+        Some(("plan", sub_matches)) => plan(&vfs, sub_matches),
+        _ => solve(&vfs),
+    }
 }
 
-fn solve(filepath: PathBuf) {
-    let mut current_path = Vec::new();
-    let mut tree_root = FileTree::Dir(HashMap::new());
-
-    if let Ok(lines) = common::read_lines(filepath) {
-        for line in lines {
-            if let Ok(line_text) = line {
-                match parse_input_output(&line_text) {
-                    InputOutput::CdRoot => {
-                        current_path.clear();
-                    }
-                    InputOutput::CdUp => {
-                        current_path.pop();
-                    }
-                    InputOutput::CdDir(dirname) => {
-                        current_path.push(dirname);
-                    }
-                    InputOutput::DirInfo(dirname) => {
-                        add_at_path(
-                            &mut tree_root,
-                            &current_path,
-                            &dirname,
-                            FileTree::Dir(HashMap::new()),
-                        );
-                    }
-                    InputOutput::FileInfo(filename, size) => {
-                        add_at_path(
-                            &mut tree_root,
-                            &current_path,
-                            &filename,
-                            FileTree::File(size),
-                        );
-                    }
-                    InputOutput::Ls => {
-                        //nothing happens
-                    }
-                    InputOutput::Unknown => {
-                        panic!("Unsupported operation")
-                    }
-                }
-            }
+fn build_vfs(filepath: &Path) -> Result<Vfs, String> {
+    let lines = common::read_lines(filepath).map_err(|err| format!("Cannot read {}: {}", filepath.display(), err))?;
+    let mut vfs = Vfs::new();
+    for (line_no, line) in lines.enumerate() {
+        if let Ok(line_text) = line {
+            vfs.apply(parse_input_output(&line_text))
+                .map_err(|err| format!("line {}: {}: {}", line_no + 1, err, line_text))?;
         }
     }
+    vfs.finish_listing();
+    Ok(vfs)
+}
+
+fn solve(vfs: &Vfs) {
     println!("Tree created!");
     let mut travel_vec: Vec<u64> = Vec::new();
-    let total_size = measure_element(&tree_root, &mut travel_vec);
+    let total_size = measure_element(&vfs.root, &mut travel_vec);
     let space_free = 70000000 - total_size;
     let space_remaining = 30000000 - space_free;
     println!("Total size: {}\nFree: {}\nTo reclaim {}", total_size, space_free, space_remaining);
@@ -96,7 +387,253 @@ fn solve(filepath: PathBuf) {
         }
     }
     println!("Size of selected dirs: {}\nBest deletion candidate {}", acc, best_del_candidate);
-    //print_tree(&tree_root);
+}
+
+fn query(vfs: &Vfs, matches: &ArgMatches) {
+    let (name, sub_matches) = matches.subcommand().unwrap();
+    let start = sub_matches
+        .try_get_one::<String>("dir")
+        .ok()
+        .flatten()
+        .map_or(Vec::new(), |dir| vfs.resolve(dir));
+    if vfs.get(&start).is_none() {
+        println!("No such file or directory {}", format_path(&start));
+        return;
+    }
+    match name {
+        "du" => {
+            for entry in vfs.entries(&start).iter().filter(|e| e.is_dir) {
+                println!("{}\t{}", entry.size, entry.path);
+            }
+        }
+        "find" => {
+            let size = sub_matches.get_one::<SizeFilter>("size");
+            let kind = sub_matches.get_one::<String>("type").map(|t| t == "d");
+            let mut entries = vfs.entries(&start);
+            entries.sort_by(|a, b| a.path.cmp(&b.path));
+            for entry in entries {
+                if size.is_none_or(|f| f.matches(entry.size)) && kind.is_none_or(|d| d == entry.is_dir) {
+                    println!("{}\t{}", entry.size, entry.path);
+                }
+            }
+        }
+        "tree" => {
+            println!("{}", render_tree(vfs.get(&start).unwrap(), &format_path(&start)));
+        }
+        "largest" => {
+            let count = *sub_matches.get_one::<usize>("count").unwrap();
+            let mut dirs: Vec<Entry> = vfs.entries(&start).into_iter().filter(|e| e.is_dir).collect();
+            dirs.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+            for entry in dirs.iter().take(count) {
+                println!("{}\t{}", entry.size, entry.path);
+            }
+        }
+        _ => unreachable!("clap should ensure we don't get here"),
+    }
+}
+
+fn export(vfs: &Vfs, matches: &ArgMatches) {
+    let result = if let Some(target) = matches.get_one::<std::path::PathBuf>("dir") {
+        let occupied = fs::read_dir(target).is_ok_and(|mut d| d.next().is_some());
+        if occupied {
+            eprintln!("{} is not empty, refusing to export there", target.display());
+            return;
+        }
+        export_dir(&vfs.root, target)
+    } else {
+        let target = matches.get_one::<std::path::PathBuf>("tar").unwrap();
+        if target.as_os_str() == "-" {
+            export_tar(&vfs.root, &mut io::BufWriter::new(io::stdout().lock()))
+        } else {
+            fs::File::create(target).and_then(|file| export_tar(&vfs.root, &mut io::BufWriter::new(file)))
+        }
+    };
+    if let Err(err) = result {
+        eprintln!("Export failed: {}", err);
+    }
+}
+
+fn plan(vfs: &Vfs, matches: &ArgMatches) {
+    let disk = *matches.get_one::<u64>("disk").unwrap();
+    let free = *matches.get_one::<u64>("free").unwrap();
+    let protected: Vec<Vec<String>> = matches
+        .get_many::<String>("protect")
+        .unwrap_or_default()
+        .map(|p| vfs.resolve(p))
+        .collect();
+
+    let used = tree_size(&vfs.root);
+    let needed = (free + used).saturating_sub(disk);
+    println!("Used: {}\nTo reclaim: {}", used, needed);
+    match plan_cleanup(&vfs.root, needed, &protected) {
+        Ok(Some((freed, dirs))) => {
+            for dir in &dirs {
+                println!("{}\t{}", tree_size(vfs.get(dir).unwrap()), format_path(dir));
+            }
+            println!("Freed: {} in {} directories", freed, dirs.len());
+        }
+        Ok(None) => println!("No cleanup plan frees {}", needed),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Space freed and the directories to delete for it
+type CleanupPlan = (u64, Vec<Vec<String>>);
+
+/// Smallest total size of non-nested directories freeing at least `needed`,
+/// skipping directories that hold or lie inside a protected path. Fails when
+/// the search cannot prove a plan minimal within PLAN_SEARCH_STEPS.
+fn plan_cleanup(tree_root: &FileTree, needed: u64, protected: &[Vec<String>]) -> Result<Option<CleanupPlan>, String> {
+    if needed == 0 {
+        return Ok(Some((0, Vec::new())));
+    }
+    let mut candidates = Vec::new();
+    collect_candidates(tree_root, &mut Vec::new(), protected, &mut 0, &mut candidates);
+    candidates.sort_by_key(|c| std::cmp::Reverse(c.size));
+    let lower = match cleanup_lower_bound(&candidates, needed) {
+        Some(Some(lower)) => lower,
+        // not even overlapping directories add up to enough
+        Some(None) => return Ok(None),
+        None => needed,
+    };
+    let mut remaining = vec![0; candidates.len() + 1];
+    for i in (0..candidates.len()).rev() {
+        remaining[i] = remaining[i + 1] + candidates[i].size;
+    }
+    let mut search =
+        CleanupSearch { candidates: &candidates, remaining, needed, taken: Vec::new(), best: None, lower, steps: 0 };
+    search.run(0, 0);
+
+    if search.steps > PLAN_SEARCH_STEPS && search.best.as_ref().is_none_or(|(freed, _)| *freed != lower) {
+        return Err(format!("No cleanup plan proven minimal within {} search steps", PLAN_SEARCH_STEPS));
+    }
+    let Some((freed, picked)) = search.best else {
+        return Ok(None);
+    };
+    let mut dirs: Vec<Vec<String>> = picked.into_iter().map(|i| candidates[i].path.clone()).collect();
+    dirs.sort();
+    Ok(Some((freed, dirs)))
+}
+
+/// Smallest sum of candidate sizes reaching needed with nesting ignored, so
+/// no plan frees less. The outer None means needed is too large to track.
+fn cleanup_lower_bound(candidates: &[Candidate], needed: u64) -> Option<Option<u64>> {
+    if needed > LOWER_BOUND_LIMIT {
+        return None;
+    }
+    let bits = needed as usize;
+    // bit n is set when some candidates add up to n, sums below needed only
+    let mut reachable = vec![0u64; bits.div_ceil(64)];
+    reachable[0] = 1;
+    let mut lower: Option<u64> = None;
+    for candidate in candidates {
+        // a smallest set reaching needed is short of it without its last member
+        let short_by = needed.saturating_sub(candidate.size) as usize;
+        if let Some(sum) = first_set_bit(&reachable, short_by, bits) {
+            let total = sum as u64 + candidate.size;
+            lower = Some(lower.map_or(total, |lower| lower.min(total)));
+        }
+        if candidate.size > 0 && candidate.size < needed {
+            shift_or(&mut reachable, candidate.size as usize);
+        }
+    }
+    Some(lower)
+}
+
+/// First set bit at or after `from` and before `end`
+fn first_set_bit(words: &[u64], from: usize, end: usize) -> Option<usize> {
+    let mut index = from / 64;
+    let mut word = *words.get(index)? & (u64::MAX << (from % 64));
+    loop {
+        if word != 0 {
+            let bit = index * 64 + word.trailing_zeros() as usize;
+            return (bit < end).then_some(bit);
+        }
+        index += 1;
+        word = *words.get(index)?;
+    }
+}
+
+/// Sets bit n + shift for every set bit n, bits past the end are dropped
+fn shift_or(words: &mut [u64], shift: usize) {
+    let (word_shift, bit_shift) = (shift / 64, shift % 64);
+    // from the top down, so every bit moves at most once
+    for k in (word_shift..words.len()).rev() {
+        let mut moved = words[k - word_shift] << bit_shift;
+        if bit_shift > 0 && k > word_shift {
+            moved |= words[k - word_shift - 1] >> (64 - bit_shift);
+        }
+        words[k] |= moved;
+    }
+}
+
+/// Returns the size of node, adding every directory that may be deleted
+fn collect_candidates(
+    node: &FileTree,
+    path: &mut Vec<String>,
+    protected: &[Vec<String>],
+    index: &mut usize,
+    acc: &mut Vec<Candidate>,
+) -> u64 {
+    let FileTree::Dir(tree) = node else {
+        return tree_size(node);
+    };
+    let start = *index;
+    *index += 1;
+    let mut size = 0;
+    for (name, element) in tree {
+        path.push(name.clone());
+        size += collect_candidates(element, path, protected, index, acc);
+        path.pop();
+    }
+    let inside_protected = protected.iter().any(|p| path.starts_with(p));
+    let holds_protected = protected.iter().any(|p| p.starts_with(path));
+    if !path.is_empty() && !inside_protected && !holds_protected {
+        acc.push(Candidate { path: path.clone(), size, start, end: *index });
+    }
+    size
+}
+
+impl CleanupSearch<'_> {
+    fn conflicts(&self, candidate: usize) -> bool {
+        self.taken.iter().any(|&t| self.candidates[t].overlaps(&self.candidates[candidate]))
+    }
+
+    fn is_done(&self) -> bool {
+        self.steps > PLAN_SEARCH_STEPS || self.best.as_ref().is_some_and(|(freed, _)| *freed == self.lower)
+    }
+
+    /// Extends the taken candidates, which free less than needed, with
+    /// candidates from index `from` on
+    fn run(&mut self, from: usize, freed: u64) {
+        self.steps += 1;
+        let gap = self.needed - freed;
+        // candidates closing the gap on their own come first, the smallest
+        // of them that fits is the only one worth trying
+        let split = from + self.candidates[from..].partition_point(|c| c.size >= gap);
+        if let Some(i) = (from..split).rev().find(|&i| !self.conflicts(i)) {
+            let total = freed + self.candidates[i].size;
+            if self.best.as_ref().is_none_or(|(best, _)| total < *best) {
+                let mut picked = self.taken.clone();
+                picked.push(i);
+                self.best = Some((total, picked));
+            }
+        }
+        for i in split..self.candidates.len() {
+            if self.is_done() || freed + self.remaining[i] < self.needed {
+                break;
+            }
+            if self.conflicts(i) {
+                continue;
+            }
+            self.taken.push(i);
+            self.run(i + 1, freed + self.candidates[i].size);
+            self.taken.pop();
+        }
+    }
 }
 
 fn measure_element(tree_root: &FileTree, travel_vec: &mut Vec<u64>) -> u64 {
@@ -113,47 +650,165 @@ fn measure_element(tree_root: &FileTree, travel_vec: &mut Vec<u64>) -> u64 {
     }
 }
 
-fn _print_tree(tree_root: &FileTree) {
-    _print_tree_inner(tree_root, 0);
+fn collect_entries(node: &FileTree, path: &mut Vec<String>, acc: &mut Vec<Entry>) -> u64 {
+    let size = match node {
+        FileTree::Dir(children) => {
+            let mut size = 0;
+            for (name, child) in children {
+                path.push(name.clone());
+                size += collect_entries(child, path, acc);
+                path.pop();
+            }
+            size
+        }
+        FileTree::File(size) => *size,
+    };
+    acc.push(Entry {
+        path: format_path(path),
+        is_dir: matches!(node, FileTree::Dir(_)),
+        size,
+    });
+    size
+}
+
+fn format_path(path: &[String]) -> String {
+    format!("/{}", path.join("/"))
 }
 
-fn _print_tree_inner(tree_root: &FileTree, indent: usize) {
+fn tree_size(tree_root: &FileTree) -> u64 {
     match tree_root {
-        FileTree::Dir(tree) => {
-            for (name, element) in tree {
-                print!("{}", "  ".repeat(indent));
-                match element {
-                    &FileTree::File(size) => {
-                        println!("{} {}", name, size)
-                    }
-                    subtree => {
-                        println!("<{}>", name);
-                        _print_tree_inner(subtree, indent + 1);
-                    }
+        FileTree::Dir(tree) => tree.values().map(tree_size).sum(),
+        FileTree::File(size) => *size,
+    }
+}
+
+/// Renders tree the way `tree --du` does, sizes in brackets before names
+fn render_tree(tree_root: &FileTree, label: &str) -> String {
+    let mut out = format!("[{:>10}]  {}\n", tree_size(tree_root), label);
+    let mut counts = (0, 0);
+    render_tree_inner(tree_root, "", &mut out, &mut counts);
+    out.push_str(&format!("\n{} directories, {} files", counts.0, counts.1));
+    out
+}
+
+fn render_tree_inner(tree_root: &FileTree, prefix: &str, out: &mut String, counts: &mut (usize, usize)) {
+    if let FileTree::Dir(tree) = tree_root {
+        for (i, (name, element)) in tree.iter().enumerate() {
+            let last = i + 1 == tree.len();
+            let branch = if last { "└── " } else { "├── " };
+            out.push_str(&format!("{}{}[{:>10}]  {}\n", prefix, branch, tree_size(element), name));
+            match element {
+                FileTree::Dir(_) => {
+                    counts.0 += 1;
+                    let nested = format!("{}{}", prefix, if last { "    " } else { "│   " });
+                    render_tree_inner(element, &nested, out, counts);
                 }
+                FileTree::File(_) => counts.1 += 1,
             }
         }
-        _ => unreachable!(),
     }
 }
 
-fn add_at_path(
-    tree_root: &mut FileTree,
-    path: &Vec<String>,
-    new_elem_name: &str,
-    to_add: FileTree,
-) {
+fn check_entry_name(name: &str) -> io::Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.contains('\0') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("refusing to export entry named '{}'", name),
+        ));
+    }
+    Ok(())
+}
+
+/// Recreates the tree under target, files become sparse files of their size
+fn export_dir(tree_root: &FileTree, target: &Path) -> io::Result<()> {
     match tree_root {
-        FileTree::Dir(children) => {
-            if path.is_empty() {
-                children.insert(new_elem_name.to_string(), to_add);
-            } else {
-                let key = &path[0];
-                let new_root = children.get_mut(&key.to_string()).unwrap();
-                add_at_path(new_root, &path[1..].to_vec(), new_elem_name, to_add)
+        FileTree::Dir(tree) => {
+            fs::create_dir_all(target)?;
+            for (name, element) in tree {
+                check_entry_name(name)?;
+                export_dir(element, &target.join(name))?;
+            }
+        }
+        FileTree::File(size) => fs::File::create(target)?.set_len(*size)?,
+    }
+    Ok(())
+}
+
+fn export_tar<W: Write>(tree_root: &FileTree, out: &mut W) -> io::Result<()> {
+    export_tar_inner(tree_root, &mut Vec::new(), out)?;
+    // archive ends with two empty blocks
+    out.write_all(&[0; TAR_BLOCK * 2])?;
+    out.flush()
+}
+
+fn export_tar_inner<W: Write>(tree_root: &FileTree, path: &mut Vec<String>, out: &mut W) -> io::Result<()> {
+    if let FileTree::Dir(tree) = tree_root {
+        for (name, element) in tree {
+            check_entry_name(name)?;
+            path.push(name.clone());
+            match element {
+                FileTree::Dir(_) => {
+                    out.write_all(&tar_header(&format!("{}/", path.join("/")), 0, b'5')?)?;
+                    export_tar_inner(element, path, out)?;
+                }
+                FileTree::File(size) => {
+                    out.write_all(&tar_header(&path.join("/"), *size, b'0')?)?;
+                    let zeros = [0; TAR_BLOCK];
+                    let mut remaining = size.div_ceil(TAR_BLOCK as u64) * TAR_BLOCK as u64;
+                    while remaining > 0 {
+                        out.write_all(&zeros)?;
+                        remaining -= TAR_BLOCK as u64;
+                    }
+                }
             }
+            path.pop();
         }
-        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+/// Builds ustar header block, long paths are split between prefix and name
+fn tar_header(path: &str, size: u64, type_flag: u8) -> io::Result<[u8; TAR_BLOCK]> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    if size >= 1 << 33 {
+        return Err(invalid(format!("{} is too large for ustar archive", path)));
+    }
+    let (prefix, name) = if path.len() <= 100 {
+        ("", path)
+    } else {
+        let trimmed = path.trim_end_matches('/');
+        trimmed
+            .char_indices()
+            .filter(|&(i, c)| c == '/' && i <= 155 && path.len() - i - 1 <= 100)
+            .map(|(i, _)| (&path[..i], &path[i + 1..]))
+            .next()
+            .ok_or_else(|| invalid(format!("path {} is too long for ustar archive", path)))?
+    };
+    let mode: &[u8] = if type_flag == b'5' { b"0000755" } else { b"0000644" };
+
+    let mut header = [0u8; TAR_BLOCK];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..107].copy_from_slice(mode);
+    header[108..115].copy_from_slice(b"0000000");
+    header[116..123].copy_from_slice(b"0000000");
+    header[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+    header[136..147].copy_from_slice(b"00000000000");
+    header[148..156].copy_from_slice(b"        ");
+    header[156] = type_flag;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+    Ok(header)
+}
+
+fn parse_size_filter(spec: &str) -> Result<SizeFilter, String> {
+    let parse = |value: &str| value.parse::<u64>().map_err(|e| format!("invalid size '{}': {}", spec, e));
+    match spec.chars().next() {
+        Some('+') => Ok(SizeFilter::Above(parse(&spec[1..])?)),
+        Some('-') => Ok(SizeFilter::Below(parse(&spec[1..])?)),
+        _ => Ok(SizeFilter::Exactly(parse(spec)?)),
     }
 }
 
@@ -163,8 +818,12 @@ fn parse_input_output(line: &str) -> InputOutput {
         "$ cd /" => InputOutput::CdRoot,
         "$ cd .." => InputOutput::CdUp,
         "$ ls" => InputOutput::Ls,
+        "$ pwd" => InputOutput::Pwd,
         cmd if cmd.starts_with("$ cd ") => InputOutput::CdDir(parse_cd_command(cmd)),
+        cmd if cmd.starts_with("$ mkdir ") => InputOutput::Mkdir(parse_last_argument(cmd)),
+        cmd if cmd.starts_with("$ rm ") => InputOutput::Rm(parse_last_argument(cmd)),
         cmd if cmd.starts_with("dir") => InputOutput::DirInfo(parse_dir_output(cmd)),
+        cmd if cmd.starts_with('/') => InputOutput::PathInfo(String::from(cmd)),
         cmd if file_line_re.is_match(cmd) => {
             let (fname, fsize) = parse_file_output(cmd);
             InputOutput::FileInfo(fname, fsize)
@@ -177,6 +836,10 @@ fn parse_cd_command(line: &str) -> String {
     String::from(line.split(' ').nth(2).unwrap())
 }
 
+fn parse_last_argument(line: &str) -> String {
+    String::from(line.rsplit(' ').next().unwrap())
+}
+
 fn parse_dir_output(line: &str) -> String {
     String::from(line.split(' ').nth(1).unwrap())
 }
@@ -189,7 +852,33 @@ fn parse_file_output(line: &str) -> (String, u64) {
 
 #[cfg(test)]
 mod _tests {
-    use super::{parse_input_output, InputOutput};
+    use std::fs;
+
+    use super::{
+        build_vfs, export_dir, export_tar, parse_input_output, parse_size_filter, plan_cleanup, render_tree, tar_header, tree_size,
+        FileTree, InputOutput, SizeFilter, TAR_BLOCK, Vfs,
+    };
+
+    fn paths(dirs: &[&str]) -> Vec<Vec<String>> {
+        dirs.iter().map(|d| Vfs::new().resolve(d)).collect()
+    }
+
+    fn run_session(session: &str) -> Vfs {
+        let mut vfs = Vfs::new();
+        for line in session.lines() {
+            vfs.apply(parse_input_output(line)).unwrap();
+        }
+        vfs.finish_listing();
+        vfs
+    }
+
+    fn dir_sizes(vfs: &Vfs) -> Vec<(String, u64)> {
+        vfs.entries(&[])
+            .into_iter()
+            .filter(|e| e.is_dir)
+            .map(|e| (e.path, e.size))
+            .collect()
+    }
 
     #[test]
     fn test_parse_input_output() {
@@ -212,5 +901,245 @@ mod _tests {
             parse_input_output("kukuryku na patyku"),
             InputOutput::Unknown
         );
+        assert_eq!(parse_input_output("$ pwd"), InputOutput::Pwd);
+        assert_eq!(parse_input_output("/a/b"), InputOutput::PathInfo(String::from("/a/b")));
+        assert_eq!(parse_input_output("$ mkdir -p a/b"), InputOutput::Mkdir(String::from("a/b")));
+        assert_eq!(parse_input_output("$ rm -r a"), InputOutput::Rm(String::from("a")));
+    }
+
+    #[test]
+    fn test_resolve_paths() {
+        let vfs = run_session("$ cd /a/b");
+        assert_eq!(vfs.cwd, vec!["a", "b"]);
+        assert_eq!(vfs.resolve("../c/./d"), vec!["a", "c", "d"]);
+        assert_eq!(vfs.resolve("/x"), vec!["x"]);
+        assert_eq!(vfs.resolve("../../../.."), Vec::<String>::new());
+        let vfs = run_session("$ cd ..\n$ cd ..\n$ cd q");
+        assert_eq!(vfs.cwd, vec!["q"]);
+    }
+
+    #[test]
+    fn test_unknown_directories_are_created() {
+        let vfs = run_session("$ cd /a/b\n$ ls\n10 f.txt\n$ cd /a\n$ ls\n5 g.txt\ndir b");
+        assert_eq!(dir_sizes(&vfs), vec![(String::from("/a/b"), 10), (String::from("/a"), 15), (String::from("/"), 15)]);
+    }
+
+    #[test]
+    fn test_relisting_replaces_contents() {
+        let vfs = run_session(
+            "$ cd /\n$ ls\ndir a\n100 x\n200 y\n$ cd a\n$ ls\n7 z\n$ cd /\n$ ls\ndir a\n150 x\ndir y",
+        );
+        assert_eq!(
+            dir_sizes(&vfs),
+            vec![(String::from("/a"), 7), (String::from("/y"), 0), (String::from("/"), 157)]
+        );
+        assert!(matches!(vfs.get(&[String::from("y")]), Some(FileTree::Dir(_))));
+    }
+
+    #[test]
+    fn test_mkdir_and_rm() {
+        let mut vfs = run_session("$ mkdir -p /a/b\n$ cd /a\n$ ls\ndir b\n3 f\n$ rm f\n$ pwd\n/a");
+        assert_eq!(dir_sizes(&vfs), vec![(String::from("/a/b"), 0), (String::from("/a"), 0), (String::from("/"), 0)]);
+        assert!(vfs.apply(parse_input_output("$ rm nothing")).is_err());
+        assert!(vfs.apply(parse_input_output("$ rm /")).is_err());
+        assert!(vfs.apply(parse_input_output("$ rm -rf /a")).is_ok());
+        assert_eq!(dir_sizes(&vfs), vec![(String::from("/"), 0)]);
+    }
+
+    #[test]
+    fn test_parse_size_filter() {
+        assert_eq!(parse_size_filter("+100"), Ok(SizeFilter::Above(100)));
+        assert_eq!(parse_size_filter("-5"), Ok(SizeFilter::Below(5)));
+        assert_eq!(parse_size_filter("42"), Ok(SizeFilter::Exactly(42)));
+        assert!(parse_size_filter("+x").is_err());
+    }
+
+    #[test]
+    fn test_render_tree() {
+        let vfs = run_session("$ cd /\n$ ls\ndir a\n10 b\n$ cd a\n$ ls\ndir c\n5 d\n$ cd c\n$ ls\n1 e");
+        assert_eq!(
+            render_tree(&vfs.root, "/"),
+            "[        16]  /\n\
+             ├── [         6]  a\n\
+             │   ├── [         1]  c\n\
+             │   │   └── [         1]  e\n\
+             │   └── [         5]  d\n\
+             └── [        10]  b\n\
+             \n2 directories, 3 files"
+        );
+    }
+
+    #[test]
+    fn test_tar_header() {
+        let header = tar_header("a/b.txt", 1000, b'0').unwrap();
+        assert_eq!(&header[..7], b"a/b.txt");
+        assert_eq!(&header[124..136], b"00000001750\0");
+        assert_eq!(&header[257..263], b"ustar\0");
+        let checksum: u32 = header
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| if (148..156).contains(&i) { b' ' as u32 } else { b as u32 })
+            .sum();
+        assert_eq!(&header[148..156], format!("{:06o}\0 ", checksum).as_bytes());
+
+        let long_path = format!("{}/{}", "d".repeat(120), "f".repeat(90));
+        let header = tar_header(&long_path, 0, b'0').unwrap();
+        assert_eq!(&header[..90], "f".repeat(90).as_bytes());
+        assert_eq!(&header[345..465], "d".repeat(120).as_bytes());
+        assert!(tar_header(&"x".repeat(101), 0, b'0').is_err());
+    }
+
+    #[test]
+    fn test_export_tar_layout() {
+        let vfs = run_session("$ cd /\n$ ls\ndir a\n700 b\n$ cd a\n$ ls\n0 c");
+        let mut archive = Vec::new();
+        export_tar(&vfs.root, &mut archive).unwrap();
+        // a/ header, a/c header, b header with two data blocks, two end blocks
+        assert_eq!(archive.len(), TAR_BLOCK * 7);
+        assert_eq!(&archive[..2], b"a/");
+        assert_eq!(&archive[TAR_BLOCK..TAR_BLOCK + 3], b"a/c");
+        assert_eq!(&archive[TAR_BLOCK * 2..TAR_BLOCK * 2 + 1], b"b");
+    }
+
+    #[test]
+    fn test_export_dir() {
+        let vfs = run_session("$ cd /\n$ ls\ndir a\n700 b\n$ cd a\n$ ls\n12345678 c");
+        let target = std::env::temp_dir().join(format!("aoc2022_day07_export_{}", std::process::id()));
+        export_dir(&vfs.root, &target).unwrap();
+        assert_eq!(fs::metadata(target.join("b")).unwrap().len(), 700);
+        assert_eq!(fs::metadata(target.join("a").join("c")).unwrap().len(), 12345678);
+        fs::remove_dir_all(&target).unwrap();
+
+        let bad = run_session("$ cd /\n$ ls\n5 ..");
+        assert!(export_dir(&bad.root, &target).is_err());
+        fs::remove_dir_all(&target).unwrap();
+    }
+
+    #[test]
+    fn test_build_vfs_errors() {
+        let path = std::env::temp_dir().join(format!("aoc2022_day07_session_{}", std::process::id()));
+        fs::write(&path, "$ cd /\n$ ls\n10 a\n$ rm b\n").unwrap();
+        assert_eq!(build_vfs(&path).err().unwrap(), "line 4: no such file or directory /b: $ rm b");
+        fs::write(&path, "$ cd /\n$ frobnicate\n").unwrap();
+        assert_eq!(build_vfs(&path).err().unwrap(), "line 2: unsupported operation: $ frobnicate");
+        fs::write(&path, "$ cd /\n$ ls\n10 a\n").unwrap();
+        assert_eq!(tree_size(&build_vfs(&path).unwrap().root), 10);
+        fs::remove_file(&path).unwrap();
+        assert!(build_vfs(&path).is_err());
+    }
+
+    #[test]
+    fn test_plan_cleanup() {
+        let vfs = run_session(
+            "$ cd /\n$ ls\ndir a\ndir b\n1 f\n$ cd a\n$ ls\ndir c\n40 g\n$ cd c\n$ ls\n30 h\n\
+             $ cd /b\n$ ls\ndir d\ndir e\n$ cd d\n$ ls\n25 i\n$ cd ../e\n$ ls\n26 j",
+        );
+        // a=70 (c=30), b=51 (d=25, e=26)
+        assert_eq!(plan_cleanup(&vfs.root, 0, &[]), Ok(Some((0, vec![]))));
+        assert_eq!(plan_cleanup(&vfs.root, 50, &[]), Ok(Some((51, paths(&["/b"])))));
+        assert_eq!(plan_cleanup(&vfs.root, 55, &[]), Ok(Some((55, paths(&["/a/c", "/b/d"])))));
+        assert_eq!(plan_cleanup(&vfs.root, 80, &[]), Ok(Some((81, paths(&["/a/c", "/b"])))));
+        assert_eq!(plan_cleanup(&vfs.root, 122, &[]), Ok(None));
+    }
+
+    #[test]
+    fn test_plan_cleanup_protected() {
+        let vfs = run_session(
+            "$ cd /\n$ ls\ndir a\ndir b\n1 f\n$ cd a\n$ ls\ndir c\n40 g\n$ cd c\n$ ls\n30 h\n\
+             $ cd /b\n$ ls\ndir d\ndir e\n$ cd d\n$ ls\n25 i\n$ cd ../e\n$ ls\n26 j",
+        );
+        assert_eq!(plan_cleanup(&vfs.root, 50, &paths(&["/b/e"])), Ok(Some((55, paths(&["/a/c", "/b/d"])))));
+        assert_eq!(plan_cleanup(&vfs.root, 50, &paths(&["/b"])), Ok(Some((70, paths(&["/a"])))));
+        assert_eq!(plan_cleanup(&vfs.root, 60, &paths(&["/a/c", "/b"])), Ok(None));
+    }
+
+    fn next_random(state: &mut u64, bound: u64) -> u64 {
+        *state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (*state >> 33) % bound
+    }
+
+    /// Terminal session building `dirs` directories below / at random
+    fn random_session(dirs: usize, max_file: u64, seed: u64) -> String {
+        let mut state = seed;
+        let mut children: Vec<Vec<usize>> = vec![Vec::new(); dirs + 1];
+        for dir in 1..=dirs {
+            children[next_random(&mut state, dir as u64) as usize].push(dir);
+        }
+        let mut session = String::new();
+        let mut pending = vec![(0, String::new())];
+        while let Some((dir, path)) = pending.pop() {
+            session += &format!("$ cd /{}\n$ ls\n", path);
+            for &child in &children[dir] {
+                session += &format!("dir d{}\n", child);
+                pending.push((child, format!("{}d{}/", path, child)));
+            }
+            for file in 0..next_random(&mut state, 5) {
+                session += &format!("{} f{}\n", 1 + next_random(&mut state, max_file), file);
+            }
+        }
+        session
+    }
+
+    /// Smallest size freed by every set of non-nested directories, needed
+    /// sizes are looked up in the result
+    fn plan_cleanup_brute_force(vfs: &Vfs) -> Vec<u64> {
+        let dirs: Vec<(Vec<String>, u64)> = vfs
+            .entries(&[])
+            .into_iter()
+            .filter(|e| e.is_dir && e.path != "/")
+            .map(|e| (vfs.resolve(&e.path), e.size))
+            .collect();
+        let mut freed: Vec<u64> = (0..1u32 << dirs.len())
+            .filter_map(|set| {
+                let picked: Vec<&(Vec<String>, u64)> = (0..dirs.len()).filter(|i| set >> i & 1 == 1).map(|i| &dirs[i]).collect();
+                let nested = picked.iter().any(|a| picked.iter().any(|b| a.0 != b.0 && a.0.starts_with(&b.0)));
+                (!nested).then(|| picked.iter().map(|dir| dir.1).sum())
+            })
+            .collect();
+        freed.sort_unstable();
+        freed
+    }
+
+    #[test]
+    fn test_plan_cleanup_random() {
+        for seed in 0..20 {
+            let vfs = run_session(&random_session(12, 1000, seed));
+            let total = tree_size(&vfs.root);
+            let options = plan_cleanup_brute_force(&vfs);
+            for needed in [1, total / 7, total / 3, total / 2, total] {
+                let plan = plan_cleanup(&vfs.root, needed, &[]).unwrap();
+                let expected = options.get(options.partition_point(|&freed| freed < needed)).copied();
+                assert_eq!(plan.as_ref().map(|p| p.0), expected, "seed {} needed {}", seed, needed);
+                if let Some((freed, dirs)) = plan {
+                    assert_eq!(dirs.iter().map(|dir| tree_size(vfs.get(dir).unwrap())).sum::<u64>(), freed);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_plan_cleanup_input_size() {
+        // about as many directories and bytes as a puzzle input
+        let vfs = run_session(&random_session(190, 240000, 7));
+        let total = tree_size(&vfs.root);
+        assert!((40000000..50000000).contains(&total));
+        let (freed, dirs) = plan_cleanup(&vfs.root, 4457863, &[]).unwrap().unwrap();
+        assert_eq!(freed, 4457863);
+        assert_eq!(dirs.iter().map(|dir| tree_size(vfs.get(dir).unwrap())).sum::<u64>(), freed);
+
+        // with only even sizes an odd amount is never hit exactly, the lower
+        // bound proves one byte more minimal
+        let session: Vec<String> = random_session(190, 240000, 7)
+            .lines()
+            .map(|line| match line.split_once(' ') {
+                Some((size, name)) if size.parse::<u64>().is_ok() => format!("{} {}", 2 * size.parse::<u64>().unwrap(), name),
+                _ => line.to_string(),
+            })
+            .collect();
+        let vfs = run_session(&session.join("\n"));
+        for needed in [4457863, 20000001] {
+            let (freed, _) = plan_cleanup(&vfs.root, needed, &[]).unwrap().unwrap();
+            assert_eq!(freed, needed + 1);
+        }
     }
 }
@@ -1,12 +1,43 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
 use std::path::PathBuf;
 
-use clap::{value_parser, ArgMatches, Command};
+use clap::{value_parser, Arg, ArgMatches, Command};
 
 use crate::common;
 
+/// Edge of the forest a tree is looked at from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    West,
+    East,
+    North,
+    South,
+}
+
+const DIRECTIONS: [Direction; 4] = [Direction::West, Direction::East, Direction::North, Direction::South];
+
+impl Direction {
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Step from a tree towards this edge
+    fn offset(self) -> (isize, isize) {
+        match self {
+            Direction::West => (0, -1),
+            Direction::East => (0, 1),
+            Direction::North => (-1, 0),
+            Direction::South => (1, 0),
+        }
+    }
+}
+
 struct Tree {
     height: u8,
     is_visible: bool,
+    visible_from: [bool; 4],
 }
 
 impl Tree {
@@ -14,27 +45,60 @@ impl Tree {
         Self {
             height,
             is_visible: false,
+            visible_from: [false; 4],
         }
     }
     pub fn set_as_visible(&mut self) {
         self.is_visible = true;
     }
+    pub fn set_as_visible_from(&mut self, direction: Direction) {
+        self.visible_from[direction.index()] = true;
+        self.set_as_visible();
+    }
 }
 
 pub fn cli() -> Command {
-    Command::new("day08").about("Elvish tree survey").arg(
-        clap::arg!(path: <PATH>)
-            .required(true)
-            .value_parser(value_parser!(std::path::PathBuf)),
-    )
+    Command::new("day08")
+        .about("Elvish tree survey")
+        .arg(
+            Arg::new("render")
+                .short('r')
+                .long("render")
+                .help("Draw visibility and scenic score heat maps")
+                .value_parser(["ansi", "ppm"]),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .help("File to write the rendering to, stdout by default")
+                .requires("render")
+                .required_if_eq("render", "ppm")
+                .value_parser(value_parser!(std::path::PathBuf)),
+        )
+        .arg(
+            Arg::new("scale")
+                .long("scale")
+                .help("Pixels per tree in PPM rendering")
+                .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..=64))
+                .default_value("4"),
+        )
+        .arg(
+            clap::arg!(path: <PATH>)
+                .required(true)
+                .value_parser(value_parser!(std::path::PathBuf)),
+        )
 }
 
 pub fn handle(matches: &ArgMatches) {
     let path = matches.get_one::<std::path::PathBuf>("path");
-    solve(path.unwrap().to_path_buf());
+    let render = matches.get_one::<String>("render").map(|r| r.as_str());
+    let output = matches.get_one::<std::path::PathBuf>("output");
+    let scale = *matches.get_one::<usize>("scale").unwrap();
+    solve(path.unwrap().to_path_buf(), render, output, scale);
 }
 
-pub fn solve(filepath: PathBuf) {
+pub fn solve(filepath: PathBuf, render: Option<&str>, output: Option<&PathBuf>, scale: usize) {
     let mut rows: Vec<Vec<u8>> = Vec::new();
 
     if let Ok(lines) = common::read_lines(filepath) {
@@ -54,14 +118,34 @@ pub fn solve(filepath: PathBuf) {
         "Result - scenic score: {}",
         get_best_scenic_score(&tree_map)
     );
+    for direction in DIRECTIONS {
+        let count: usize = tree_map
+            .iter()
+            .map(|row| row.iter().filter(|t| t.visible_from[direction.index()]).count())
+            .sum();
+        println!("Visible from {:?}: {}", direction, count);
+    }
+
+    let Some(render) = render else {
+        return;
+    };
+    let distances = calculate_view_distances(&tree_map);
+    let rendering = match render {
+        "ansi" => render_ansi(&tree_map, &distances).into_bytes(),
+        _ => render_ppm(&tree_map, &distances, scale),
+    };
+    let written = match output {
+        Some(target) => fs::write(target, rendering),
+        None => io::stdout().lock().write_all(&rendering),
+    };
+    if let Err(err) = written {
+        eprintln!("Cannot write rendering: {}", err);
+    }
 }
-fn get_best_scenic_score(tree_map: &Vec<Vec<Tree>>) -> usize {
-    let x_size = tree_map.len();
-    let y_size = tree_map[0].len();
+fn get_best_scenic_score(tree_map: &[Vec<Tree>]) -> usize {
     let mut best_score: usize = 1;
-    for x in 0..x_size {
-        for y in 0..y_size {
-            let score = calculate_scenic_score_on_tree_map(tree_map, x, y);
+    for row in calculate_scenic_scores(tree_map) {
+        for score in row {
             if score > best_score {
                 best_score = score;
             }
@@ -70,69 +154,24 @@ fn get_best_scenic_score(tree_map: &Vec<Vec<Tree>>) -> usize {
     best_score
 }
 
-fn calculate_scenic_score_on_tree_map(tree_map: &Vec<Vec<Tree>>, x: usize, y: usize) -> usize {
-    let height = tree_map[x][y].height;
-    let x_size = tree_map.len();
-    let y_size = tree_map[0].len();
-    let mut score: usize = 1;
+/// Product of the view distances of every tree, multiplied in as each sweep
+/// reaches it so the per-direction distances are never stored
+fn calculate_scenic_scores(tree_map: &[Vec<Tree>]) -> Vec<Vec<usize>> {
+    let heights = tree_heights(tree_map);
+    let mut scores: Vec<Vec<usize>> = heights.iter().map(|row| vec![1; row.len()]).collect();
+    sweep_all_directions(&heights, |(x, y), _, distance, _| scores[x][y] *= distance);
+    scores
+}
 
-    let mut direction_acc: usize = 0;
-    let mut cursor = x;
-    loop {
-        if cursor == x_size - 1 {
-            score *= direction_acc;
-            break;
-        }
-        cursor += 1;
-        direction_acc += 1;
-        if tree_map[cursor][y].height >= height {
-            score *= direction_acc;
-            break;
-        }
-    }
-    cursor = x;
-    direction_acc = 0;
-    loop {
-        if cursor == 0 {
-            score *= direction_acc;
-            break;
-        }
-        cursor -= 1;
-        direction_acc += 1;
-        if tree_map[cursor][y].height >= height {
-            score *= direction_acc;
-            break;
-        }
-    }
-    cursor = y;
-    direction_acc = 0;
-    loop {
-        if cursor == y_size - 1 {
-            score *= direction_acc;
-            break;
-        }
-        cursor += 1;
-        direction_acc += 1;
-        if tree_map[x][cursor].height >= height {
-            score *= direction_acc;
-            break;
-        }
-    }
-    cursor = y;
-    direction_acc = 0;
-    loop {
-        if cursor == 0 {
-            score *= direction_acc;
-            break;
-        }
-        cursor -= 1;
-        direction_acc += 1;
-        if tree_map[x][cursor].height >= height {
-            score *= direction_acc;
-            break;
-        }
-    }
-    score
+/// How many trees each tree sees towards every edge, indexed by Direction,
+/// only needed for rendering
+fn calculate_view_distances(tree_map: &[Vec<Tree>]) -> Vec<Vec<[usize; 4]>> {
+    let heights = tree_heights(tree_map);
+    let mut distances: Vec<Vec<[usize; 4]>> = heights.iter().map(|row| vec![[0; 4]; row.len()]).collect();
+    sweep_all_directions(&heights, |(x, y), direction, distance, _| {
+        distances[x][y][direction.index()] = distance
+    });
+    distances
 }
 
 fn count_tree_map_visibility(tree_map: &Vec<Vec<Tree>>) -> u64 {
@@ -149,47 +188,188 @@ fn count_tree_map_visibility(tree_map: &Vec<Vec<Tree>>) -> u64 {
     count
 }
 
-fn update_tree_map_visibility(tree_map: &mut Vec<Vec<Tree>>) {
-    let x_size = tree_map.len();
-    let y_size = tree_map[0].len();
+fn update_tree_map_visibility(tree_map: &mut [Vec<Tree>]) {
+    let heights = tree_heights(tree_map);
+    sweep_all_directions(&heights, |(x, y), direction, _, visible| {
+        if visible {
+            tree_map[x][y].set_as_visible_from(direction);
+        }
+    });
+}
+
+fn tree_heights(tree_map: &[Vec<Tree>]) -> Vec<Vec<u8>> {
+    tree_map
+        .iter()
+        .map(|row| row.iter().map(|tree| tree.height).collect())
+        .collect()
+}
 
-    let mut highest_so_far;
+/// Looks along every row and column from both ends, see sweep_line
+fn sweep_all_directions<F>(heights: &[Vec<u8>], mut on_tree: F)
+where
+    F: FnMut((usize, usize), Direction, usize, bool),
+{
+    let x_size = heights.len();
+    let y_size = heights[0].len();
     for x in 0..x_size {
-        highest_so_far = tree_map[x][0].height;
-        tree_map[x][0].set_as_visible();
-        for y in 1..y_size {
-            if highest_so_far < tree_map[x][y].height {
-                tree_map[x][y].set_as_visible();
-                highest_so_far = tree_map[x][y].height;
-            }
+        sweep_line(heights, y_size, |step| (x, step), Direction::West, &mut on_tree);
+        sweep_line(heights, y_size, |step| (x, y_size - 1 - step), Direction::East, &mut on_tree);
+    }
+    for y in 0..y_size {
+        sweep_line(heights, x_size, |step| (step, y), Direction::North, &mut on_tree);
+        sweep_line(heights, x_size, |step| (x_size - 1 - step, y), Direction::South, &mut on_tree);
+    }
+}
+
+/// Walks a line of trees starting at `direction` edge keeping a monotonic
+/// stack of the ones not yet overshadowed. For each tree reports how far it
+/// sees back towards the edge and whether it is visible from there.
+fn sweep_line<P, F>(heights: &[Vec<u8>], length: usize, position: P, direction: Direction, on_tree: &mut F)
+where
+    P: Fn(usize) -> (usize, usize),
+    F: FnMut((usize, usize), Direction, usize, bool),
+{
+    let mut stack: Vec<(usize, u8)> = Vec::new();
+    for step in 0..length {
+        let (x, y) = position(step);
+        let height = heights[x][y];
+        while stack.last().is_some_and(|&(_, h)| h < height) {
+            stack.pop();
         }
-        highest_so_far = tree_map[x][y_size - 1].height;
-        tree_map[x][y_size - 1].set_as_visible();
-        for y in 2..=y_size {
-            if highest_so_far < tree_map[x][y_size - y].height {
-                tree_map[x][y_size - y].set_as_visible();
-                highest_so_far = tree_map[x][y_size - y].height;
-            }
+        match stack.last() {
+            Some(&(blocker, _)) => on_tree((x, y), direction, step - blocker, false),
+            None => on_tree((x, y), direction, step, true),
         }
+        stack.push((step, height));
     }
-    for y in 0..y_size {
-        highest_so_far = tree_map[0][y].height;
-        tree_map[0][y].set_as_visible();
-        for x in 1..x_size {
-            if highest_so_far < tree_map[x][y].height {
-                tree_map[x][y].set_as_visible();
-                highest_so_far = tree_map[x][y].height;
+}
+
+/// Tree with the best scenic score, first one in reading order on ties
+fn best_tree(distances: &[Vec<[usize; 4]>]) -> (usize, usize) {
+    let mut best = ((0, 0), 0);
+    for (x, row) in distances.iter().enumerate() {
+        for (y, d) in row.iter().enumerate() {
+            let score: usize = d.iter().product();
+            if score > best.1 {
+                best = ((x, y), score);
             }
         }
-        highest_so_far = tree_map[x_size - 1][y].height;
-        tree_map[x_size - 1][y].set_as_visible();
-        for x in 2..=x_size {
-            if highest_so_far < tree_map[x_size - x][y].height {
-                tree_map[x_size - x][y].set_as_visible();
-                highest_so_far = tree_map[x_size - x][y].height;
+    }
+    best.0
+}
+
+/// Trees seen from the given tree, blocking trees included
+fn sight_lines(distances: &[Vec<[usize; 4]>], (x, y): (usize, usize)) -> HashSet<(usize, usize)> {
+    let mut cells = HashSet::new();
+    for direction in DIRECTIONS {
+        let (dx, dy) = direction.offset();
+        for step in 1..=distances[x][y][direction.index()] as isize {
+            cells.insert(((x as isize + dx * step) as usize, (y as isize + dy * step) as usize));
+        }
+    }
+    cells
+}
+
+/// Colour of a tree by the edges it can be seen from, west/east drive red,
+/// north/south drive green, any visibility adds blue so edge-less trees stay black
+fn visibility_color(tree: &Tree) -> (u8, u8, u8) {
+    let v = |d: Direction| tree.visible_from[d.index()] as u8;
+    let red = v(Direction::West) * 140 + v(Direction::East) * 115;
+    let green = v(Direction::North) * 140 + v(Direction::South) * 115;
+    let blue = if tree.is_visible { 90 } else { 0 };
+    (red, green, blue)
+}
+
+/// Black-red-yellow-white ramp on a logarithmic scale
+fn heat_color(score: usize, max_score: usize) -> (u8, u8, u8) {
+    if score == 0 || max_score <= 1 {
+        return (0, 0, 0);
+    }
+    let level = ((score as f64).ln_1p() / (max_score as f64).ln_1p() * 765.0) as u32;
+    let channel = |from: u32| level.saturating_sub(from).min(255) as u8;
+    (channel(0), channel(255), channel(510))
+}
+
+const BEST_COLOR: (u8, u8, u8) = (255, 0, 255);
+const SIGHT_COLOR: (u8, u8, u8) = (0, 200, 255);
+
+fn scenic_cell_color(
+    distances: &[Vec<[usize; 4]>],
+    max_score: usize,
+    best: (usize, usize),
+    sight: &HashSet<(usize, usize)>,
+    cell: (usize, usize),
+) -> (u8, u8, u8) {
+    if cell == best {
+        BEST_COLOR
+    } else if sight.contains(&cell) {
+        SIGHT_COLOR
+    } else {
+        heat_color(distances[cell.0][cell.1].iter().product(), max_score)
+    }
+}
+
+/// Two terminal panels, edge visibility and scenic score heat map,
+/// with the best tree and what it sees highlighted on the latter
+fn render_ansi(tree_map: &[Vec<Tree>], distances: &[Vec<[usize; 4]>]) -> String {
+    let best = best_tree(distances);
+    let max_score: usize = distances[best.0][best.1].iter().product();
+    let sight = sight_lines(distances, best);
+    let paint = |(r, g, b): (u8, u8, u8), height: u8| format!("\x1b[48;2;{};{};{}m\x1b[97m{}", r, g, b, height);
+
+    let mut out = String::from("Visibility (red: west/east, green: north/south)\n");
+    for row in tree_map {
+        for tree in row {
+            out.push_str(&paint(visibility_color(tree), tree.height));
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out.push_str(&format!(
+        "Scenic score (best {} at row {} column {}, magenta, sight lines cyan)\n",
+        max_score, best.0, best.1
+    ));
+    for (x, row) in tree_map.iter().enumerate() {
+        for (y, tree) in row.iter().enumerate() {
+            let color = scenic_cell_color(distances, max_score, best, &sight, (x, y));
+            out.push_str(&paint(color, tree.height));
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+/// Binary PPM with visibility panel on the left and scenic heat map on the right
+fn render_ppm(tree_map: &[Vec<Tree>], distances: &[Vec<[usize; 4]>], scale: usize) -> Vec<u8> {
+    let best = best_tree(distances);
+    let max_score: usize = distances[best.0][best.1].iter().product();
+    let sight = sight_lines(distances, best);
+    let columns = tree_map[0].len();
+    let gap = 1;
+    let width = (columns * 2 + gap) * scale;
+    let height = tree_map.len() * scale;
+
+    let mut out = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    for (x, row) in tree_map.iter().enumerate() {
+        let mut line: Vec<u8> = Vec::with_capacity(width * 3);
+        let mut push = |(r, g, b): (u8, u8, u8)| {
+            for _ in 0..scale {
+                line.extend_from_slice(&[r, g, b]);
             }
+        };
+        for tree in row {
+            push(visibility_color(tree));
+        }
+        for _ in 0..gap {
+            push((255, 255, 255));
+        }
+        for y in 0..columns {
+            push(scenic_cell_color(distances, max_score, best, &sight, (x, y)));
+        }
+        for _ in 0..scale {
+            out.extend_from_slice(&line);
         }
     }
+    out
 }
 
 fn height_map_to_tree_map(map: &Vec<Vec<u8>>) -> Vec<Vec<Tree>> {
@@ -210,9 +390,134 @@ fn line_to_u8_vec(line: &str) -> Vec<u8> {
 #[cfg(test)]
 mod tests {
     use super::{
-        calculate_scenic_score_on_tree_map, count_tree_map_visibility, height_map_to_tree_map,
-        update_tree_map_visibility,
+        best_tree, calculate_scenic_scores, calculate_view_distances, count_tree_map_visibility,
+        get_best_scenic_score, height_map_to_tree_map, render_ppm, sight_lines, update_tree_map_visibility,
+        Direction, Tree,
     };
+
+    // Straightforward walk-outward implementations kept as reference
+    fn calculate_scenic_score_on_tree_map(tree_map: &Vec<Vec<Tree>>, x: usize, y: usize) -> usize {
+        let height = tree_map[x][y].height;
+        let x_size = tree_map.len();
+        let y_size = tree_map[0].len();
+        let mut score: usize = 1;
+
+        let mut direction_acc: usize = 0;
+        let mut cursor = x;
+        loop {
+            if cursor == x_size - 1 {
+                score *= direction_acc;
+                break;
+            }
+            cursor += 1;
+            direction_acc += 1;
+            if tree_map[cursor][y].height >= height {
+                score *= direction_acc;
+                break;
+            }
+        }
+        cursor = x;
+        direction_acc = 0;
+        loop {
+            if cursor == 0 {
+                score *= direction_acc;
+                break;
+            }
+            cursor -= 1;
+            direction_acc += 1;
+            if tree_map[cursor][y].height >= height {
+                score *= direction_acc;
+                break;
+            }
+        }
+        cursor = y;
+        direction_acc = 0;
+        loop {
+            if cursor == y_size - 1 {
+                score *= direction_acc;
+                break;
+            }
+            cursor += 1;
+            direction_acc += 1;
+            if tree_map[x][cursor].height >= height {
+                score *= direction_acc;
+                break;
+            }
+        }
+        cursor = y;
+        direction_acc = 0;
+        loop {
+            if cursor == 0 {
+                score *= direction_acc;
+                break;
+            }
+            cursor -= 1;
+            direction_acc += 1;
+            if tree_map[x][cursor].height >= height {
+                score *= direction_acc;
+                break;
+            }
+        }
+        score
+    }
+
+    fn reference_visibility(tree_map: &mut Vec<Vec<Tree>>) {
+        let x_size = tree_map.len();
+        let y_size = tree_map[0].len();
+
+        let mut highest_so_far;
+        for x in 0..x_size {
+            highest_so_far = tree_map[x][0].height;
+            tree_map[x][0].set_as_visible();
+            for y in 1..y_size {
+                if highest_so_far < tree_map[x][y].height {
+                    tree_map[x][y].set_as_visible();
+                    highest_so_far = tree_map[x][y].height;
+                }
+            }
+            highest_so_far = tree_map[x][y_size - 1].height;
+            tree_map[x][y_size - 1].set_as_visible();
+            for y in 2..=y_size {
+                if highest_so_far < tree_map[x][y_size - y].height {
+                    tree_map[x][y_size - y].set_as_visible();
+                    highest_so_far = tree_map[x][y_size - y].height;
+                }
+            }
+        }
+        for y in 0..y_size {
+            highest_so_far = tree_map[0][y].height;
+            tree_map[0][y].set_as_visible();
+            for x in 1..x_size {
+                if highest_so_far < tree_map[x][y].height {
+                    tree_map[x][y].set_as_visible();
+                    highest_so_far = tree_map[x][y].height;
+                }
+            }
+            highest_so_far = tree_map[x_size - 1][y].height;
+            tree_map[x_size - 1][y].set_as_visible();
+            for x in 2..=x_size {
+                if highest_so_far < tree_map[x_size - x][y].height {
+                    tree_map[x_size - x][y].set_as_visible();
+                    highest_so_far = tree_map[x_size - x][y].height;
+                }
+            }
+        }
+    }
+
+    /// Pseudo random forest, heights spread evenly over 0 to 9
+    fn generate_forest(x_size: usize, y_size: usize, seed: u64) -> Vec<Vec<u8>> {
+        let mut state = seed;
+        (0..x_size)
+            .map(|_| {
+                (0..y_size)
+                    .map(|_| {
+                        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                        ((state >> 33) % 10) as u8
+                    })
+                    .collect()
+            })
+            .collect()
+    }
     #[test]
     fn test_validation() {
         let test_map: Vec<Vec<u8>> = vec![
@@ -238,5 +543,71 @@ mod tests {
         let tree_map = height_map_to_tree_map(&test_map);
         assert_eq!(calculate_scenic_score_on_tree_map(&tree_map, 3, 2), 8);
         assert_eq!(calculate_scenic_score_on_tree_map(&tree_map, 1, 2), 4);
+        let scores = calculate_scenic_scores(&tree_map);
+        assert_eq!(scores[3][2], 8);
+        assert_eq!(scores[1][2], 4);
+        assert_eq!(get_best_scenic_score(&tree_map), 8);
+    }
+    #[test]
+    fn test_sweeps_match_reference() {
+        for (x_size, y_size, seed) in [(1, 1, 1), (1, 7, 2), (9, 1, 3), (23, 31, 4), (64, 64, 5)] {
+            let heights = generate_forest(x_size, y_size, seed);
+            let mut fast = height_map_to_tree_map(&heights);
+            let mut reference = height_map_to_tree_map(&heights);
+            update_tree_map_visibility(&mut fast);
+            reference_visibility(&mut reference);
+            let flags = |map: &Vec<Vec<Tree>>| -> Vec<Vec<bool>> {
+                map.iter().map(|row| row.iter().map(|t| t.is_visible).collect()).collect()
+            };
+            assert_eq!(flags(&fast), flags(&reference));
+
+            for (x, row) in calculate_scenic_scores(&fast).iter().enumerate() {
+                for (y, score) in row.iter().enumerate() {
+                    assert_eq!(*score, calculate_scenic_score_on_tree_map(&reference, x, y));
+                }
+            }
+        }
+    }
+    #[test]
+    fn test_visibility_per_direction() {
+        let test_map: Vec<Vec<u8>> = vec![
+            vec![3, 0, 3, 7, 3],
+            vec![2, 5, 5, 1, 2],
+            vec![6, 5, 3, 3, 2],
+            vec![3, 3, 5, 4, 9],
+            vec![3, 5, 3, 9, 0],
+        ];
+        let mut tree_map = height_map_to_tree_map(&test_map);
+        update_tree_map_visibility(&mut tree_map);
+        // top-left 5 is visible from north and west only
+        assert_eq!(tree_map[1][1].visible_from, [true, false, true, false]);
+        // top-middle 5 is visible from north and east
+        assert_eq!(tree_map[1][2].visible_from, [false, true, true, false]);
+        assert_eq!(tree_map[2][2].visible_from, [false; 4]);
+        assert!(tree_map[0][0].visible_from[Direction::West as usize]);
+        assert!(tree_map[0][0].visible_from[Direction::North as usize]);
+    }
+    #[test]
+    fn test_best_tree_sight_lines() {
+        let test_map: Vec<Vec<u8>> = vec![
+            vec![3, 0, 3, 7, 3],
+            vec![2, 5, 5, 1, 2],
+            vec![6, 5, 3, 3, 2],
+            vec![3, 3, 5, 4, 9],
+            vec![3, 5, 3, 9, 0],
+        ];
+        let tree_map = height_map_to_tree_map(&test_map);
+        let distances = calculate_view_distances(&tree_map);
+        let best = best_tree(&distances);
+        assert_eq!(best, (3, 2));
+        assert_eq!(distances[3][2], [2, 2, 2, 1]);
+        let mut sight: Vec<(usize, usize)> = sight_lines(&distances, best).into_iter().collect();
+        sight.sort();
+        assert_eq!(sight, vec![(1, 2), (2, 2), (3, 0), (3, 1), (3, 3), (3, 4), (4, 2)]);
+
+        let image = render_ppm(&tree_map, &distances, 2);
+        let header = b"P6\n22 10\n255\n";
+        assert_eq!(&image[..header.len()], header);
+        assert_eq!(image.len(), header.len() + 22 * 10 * 3);
     }
 }
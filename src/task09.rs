@@ -1,19 +1,119 @@
-use std::{collections::HashSet, path::PathBuf};
+use std::fmt::Write as _;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::PathBuf,
+};
 
-use clap::{value_parser, ArgMatches, Command};
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
 
-use crate::common;
+use crate::common::{self, IntervalSet};
+
+/// Vertical, horizontal position, up and right are positive
+type Pos = (i64, i64);
 
 enum Movement {
     Up(usize),
     Down(usize),
     Left(usize),
     Right(usize),
+    UpLeft(usize),
+    UpRight(usize),
+    DownLeft(usize),
+    DownRight(usize),
+}
+
+impl Movement {
+    fn repeats(&self) -> usize {
+        match self {
+            Movement::Up(moves)
+            | Movement::Down(moves)
+            | Movement::Left(moves)
+            | Movement::Right(moves)
+            | Movement::UpLeft(moves)
+            | Movement::UpRight(moves)
+            | Movement::DownLeft(moves)
+            | Movement::DownRight(moves) => *moves,
+        }
+    }
+}
+
+/// Cells visited by a knot kept as segments, horizontal runs and single
+/// cells per row, vertical runs per column
+#[derive(Default)]
+struct SegmentTrail {
+    rows: BTreeMap<i64, IntervalSet>,
+    columns: BTreeMap<i64, IntervalSet>,
+}
+
+impl SegmentTrail {
+    fn insert_point(&mut self, pos: Pos) {
+        self.rows.entry(pos.0).or_default().insert(pos.1, pos.1);
+    }
+
+    /// Inserts cells between two positions sharing a row or a column
+    fn insert_run(&mut self, from: Pos, to: Pos) {
+        if from.0 == to.0 {
+            self.rows.entry(from.0).or_default().insert(from.1.min(to.1), from.1.max(to.1));
+        } else {
+            self.columns.entry(from.1).or_default().insert(from.0.min(to.0), from.0.max(to.0));
+        }
+    }
+
+    /// Number of distinct cells, those covered by both a row and a column segment count once
+    fn len(&self) -> u64 {
+        let mut total: u64 = self.rows.values().chain(self.columns.values()).map(|s| s.len()).sum();
+        for (column, rows) in &self.columns {
+            for &(start, end) in rows.ranges() {
+                for (_, columns) in self.rows.range(start..=end) {
+                    if columns.contains(*column) {
+                        total -= 1;
+                    }
+                }
+            }
+        }
+        total
+    }
+}
+
+/// Knot positions after simulating a rope, knot 0 is the head
+struct RopeSimulation {
+    positions: Vec<Pos>,
+    visited: Vec<HashSet<Pos>>,
+    trace: Vec<Vec<Pos>>,
 }
 
 pub fn cli() -> Command {
     Command::new("day09")
         .about("Elvish rope thought experiments")
+        .arg(
+            Arg::new("knots")
+                .short('k')
+                .long("knots")
+                .help("Knot count for the detailed simulation")
+                .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..))
+                .default_value("10"),
+        )
+        .arg(
+            Arg::new("segments")
+                .short('s')
+                .long("segments")
+                .help("Move straightened rope in bulk, for very long moves")
+                .conflicts_with_all(["knots", "trace", "map"])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("trace")
+                .long("trace")
+                .help("Write every knot position after each step to CSV file")
+                .value_parser(value_parser!(std::path::PathBuf)),
+        )
+        .arg(
+            Arg::new("map")
+                .long("map")
+                .help("Draw positions visited by given knot, 0 is the head")
+                .value_parser(value_parser!(usize)),
+        )
         .arg(
             clap::arg!(path: <PATH>)
                 .required(true)
@@ -23,7 +123,19 @@ pub fn cli() -> Command {
 
 pub fn handle(matches: &ArgMatches) {
     let path = matches.get_one::<std::path::PathBuf>("path");
-    solve(path.unwrap().to_path_buf());
+    let detailed = matches.value_source("knots") == Some(clap::parser::ValueSource::CommandLine)
+        || matches.contains_id("trace")
+        || matches.contains_id("map");
+    if matches.get_flag("segments") {
+        solve_segments(path.unwrap().to_path_buf());
+    } else if detailed {
+        let knots = *matches.get_one::<usize>("knots").unwrap();
+        let trace = matches.get_one::<std::path::PathBuf>("trace");
+        let map = matches.get_one::<usize>("map").copied();
+        solve_detailed(path.unwrap().to_path_buf(), knots, trace, map);
+    } else {
+        solve(path.unwrap().to_path_buf());
+    }
 }
 
 pub fn solve(filepath: PathBuf) {
@@ -40,56 +152,133 @@ pub fn solve(filepath: PathBuf) {
     }
 }
 
-fn populate_movemt(movements: &Vec<Movement>, rope_len: usize) -> HashSet<(i32, i32)> {
-    let mut positions: Vec<(i32, i32)> = vec![(0, 0); rope_len];
-    let mut tail_pos_set: HashSet<(i32, i32)> = HashSet::new();
-    tail_pos_set.insert(*positions.last().unwrap());
+fn solve_segments(filepath: PathBuf) {
+    if let Ok(lines) = common::read_lines(filepath) {
+        let movements: Vec<Movement> = lines.map(|line| line_to_movent(&line.unwrap())).collect();
+        for rope_len in [2, 10] {
+            let trails = simulate_rope_segments(&movements, rope_len);
+            println!("{} unique positions with {} len", trails.last().unwrap().len(), rope_len);
+        }
+    }
+}
+
+fn solve_detailed(filepath: PathBuf, knots: usize, trace: Option<&PathBuf>, map: Option<usize>) {
+    let Ok(lines) = common::read_lines(filepath) else {
+        return;
+    };
+    let movements: Vec<Movement> = lines.map(|line| line_to_movent(&line.unwrap())).collect();
+    let simulation = simulate_rope(&movements, knots, trace.is_some());
+    for (knot, visited) in simulation.visited.iter().enumerate() {
+        println!("knot {}: {} unique positions", knot, visited.len());
+    }
+    println!("Final positions (x,y): {:?}", simulation.positions.iter().map(|p| (p.1, p.0)).collect::<Vec<_>>());
+    if let Some(target) = trace {
+        if let Err(err) = fs::write(target, trace_to_csv(&simulation.trace)) {
+            eprintln!("Cannot write trace: {}", err);
+        }
+    }
+    if let Some(knot) = map {
+        match simulation.visited.get(knot) {
+            Some(visited) => println!("{}", render_trail(visited)),
+            None => eprintln!("Rope has only {} knots", knots),
+        }
+    }
+}
+
+fn populate_movemt(movements: &Vec<Movement>, rope_len: usize) -> HashSet<Pos> {
+    simulate_rope(movements, rope_len, false).visited.pop().unwrap()
+}
+
+fn simulate_rope(movements: &[Movement], rope_len: usize, keep_trace: bool) -> RopeSimulation {
+    let mut positions: Vec<Pos> = vec![(0, 0); rope_len];
+    let mut visited: Vec<HashSet<Pos>> = vec![HashSet::from([(0, 0)]); rope_len];
+    let mut trace: Vec<Vec<Pos>> = Vec::new();
+    if keep_trace {
+        trace.push(positions.clone());
+    }
 
-    // let mut delta = Box::new(|x: (i32, i32)| x);
-    let mut repeats: usize;
     for movement in movements {
-        match movement {
-            Movement::Up(moves) => {
-                repeats = *moves;
-            }
-            Movement::Down(moves) => {
-                repeats = *moves;
-            }
-            Movement::Left(moves) => {
-                repeats = *moves;
-            }
-            Movement::Right(moves) => {
-                repeats = *moves;
+        for _ in 0..movement.repeats() {
+            step_rope(&mut positions, movement, |knot, pos| {
+                visited[knot].insert(pos);
+            });
+            if keep_trace {
+                trace.push(positions.clone());
             }
         }
-        for _ in 0..repeats {
-            positions[0] = direction_single_shift(&positions[0], &movement);
-            for i in 0..rope_len - 1 {
-                // let new_head_pos = direction_single_shift(&positions[i], &movement);
-                if check_if_needs_move(&positions[i], &positions[i + 1]) {
-                    positions[i + 1] = new_tail_pos(&positions[i], &positions[i+1]);
+    }
+    RopeSimulation { positions, visited, trace }
+}
+
+/// Same as simulate_rope, but once the rope lies straight behind the head
+/// along an up/down/left/right move, the rest of the move shifts every knot
+/// at once and is stored as a segment. Diagonal moves go step by step.
+fn simulate_rope_segments(movements: &[Movement], rope_len: usize) -> Vec<SegmentTrail> {
+    let mut positions: Vec<Pos> = vec![(0, 0); rope_len];
+    let mut trails: Vec<SegmentTrail> = (0..rope_len).map(|_| SegmentTrail::default()).collect();
+    for trail in trails.iter_mut() {
+        trail.insert_point((0, 0));
+    }
+
+    for movement in movements {
+        let delta = direction_single_shift(&(0, 0), movement);
+        let orthogonal = delta.0 == 0 || delta.1 == 0;
+        let mut remaining = movement.repeats() as i64;
+        while remaining > 0 {
+            if orthogonal && is_straight(&positions, delta) {
+                for (pos, trail) in positions.iter_mut().zip(trails.iter_mut()) {
+                    let end = (pos.0 + delta.0 * remaining, pos.1 + delta.1 * remaining);
+                    trail.insert_run((pos.0 + delta.0, pos.1 + delta.1), end);
+                    *pos = end;
                 }
+                break;
             }
-            tail_pos_set.insert(*positions.last().unwrap());
+            step_rope(&mut positions, movement, |knot, pos| trails[knot].insert_point(pos));
+            remaining -= 1;
         }
     }
-    tail_pos_set
+    trails
+}
+
+/// Every knot sits right behind the previous one when looking along delta
+fn is_straight(positions: &[Pos], delta: Pos) -> bool {
+    positions
+        .windows(2)
+        .all(|pair| pair[1] == (pair[0].0 - delta.0, pair[0].1 - delta.1))
 }
 
-fn direction_single_shift(pos: &(i32, i32), mvmnt: &Movement) -> (i32, i32) {
+/// Moves head by one step, reporting every knot that changed position
+fn step_rope<F: FnMut(usize, Pos)>(positions: &mut [Pos], movement: &Movement, mut on_move: F) {
+    positions[0] = direction_single_shift(&positions[0], movement);
+    on_move(0, positions[0]);
+    for i in 0..positions.len() - 1 {
+        if !check_if_needs_move(&positions[i], &positions[i + 1]) {
+            // knots further down the rope cannot move either
+            break;
+        }
+        positions[i + 1] = new_tail_pos(&positions[i], &positions[i+1]);
+        on_move(i + 1, positions[i + 1]);
+    }
+}
+
+fn direction_single_shift(pos: &Pos, mvmnt: &Movement) -> Pos {
     match mvmnt {
         Movement::Up(_) => (pos.0 + 1, pos.1),
         Movement::Down(_) => (pos.0 - 1, pos.1),
         Movement::Left(_) => (pos.0, pos.1 - 1),
         Movement::Right(_) => (pos.0, pos.1 + 1),
+        Movement::UpLeft(_) => (pos.0 + 1, pos.1 - 1),
+        Movement::UpRight(_) => (pos.0 + 1, pos.1 + 1),
+        Movement::DownLeft(_) => (pos.0 - 1, pos.1 - 1),
+        Movement::DownRight(_) => (pos.0 - 1, pos.1 + 1),
     }
 }
 
-fn check_if_needs_move(head_new: &(i32, i32), tail: &(i32, i32)) -> bool {
+fn check_if_needs_move(head_new: &Pos, tail: &Pos) -> bool {
     (head_new.0 - tail.0).abs() > 1 || (head_new.1 - tail.1).abs() > 1
 }
 
-fn new_tail_pos(head: &(i32,i32), tail: &(i32,i32)) -> (i32,i32) {
+fn new_tail_pos(head: &Pos, tail: &Pos) -> Pos {
     if check_if_needs_move(&head, &tail){
         if head.0 == tail.0 {
             return (tail.0, tail.1+(head.1 - tail.1).signum());
@@ -106,8 +295,50 @@ fn new_tail_pos(head: &(i32,i32), tail: &(i32,i32)) -> (i32,i32) {
     }
 }
 
+/// One row per step, x grows right and y grows up like in the puzzle drawings
+fn trace_to_csv(trace: &[Vec<Pos>]) -> String {
+    let knots = trace.first().map_or(0, |p| p.len());
+    let mut out = String::from("step");
+    for knot in 0..knots {
+        write!(out, ",knot{}_x,knot{}_y", knot, knot).unwrap();
+    }
+    out.push('\n');
+    for (step, positions) in trace.iter().enumerate() {
+        write!(out, "{}", step).unwrap();
+        for pos in positions {
+            write!(out, ",{},{}", pos.1, pos.0).unwrap();
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Draws visited positions as `#`, starting point as `s`
+fn render_trail(visited: &HashSet<Pos>) -> String {
+    let rows = visited.iter().map(|p| p.0).chain([0]);
+    let cols = visited.iter().map(|p| p.1).chain([0]);
+    let (min_row, max_row) = (rows.clone().min().unwrap(), rows.max().unwrap());
+    let (min_col, max_col) = (cols.clone().min().unwrap(), cols.max().unwrap());
+    let mut lines = Vec::new();
+    for row in (min_row..=max_row).rev() {
+        let line: String = (min_col..=max_col)
+            .map(|col| match (row, col) {
+                (0, 0) => 's',
+                pos if visited.contains(&pos) => '#',
+                _ => '.',
+            })
+            .collect();
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
 fn line_to_movent(line: &str) -> Movement {
     match line {
+        lmatched if lmatched.starts_with("UL") => Movement::UpLeft(parse_line_number(lmatched)),
+        lmatched if lmatched.starts_with("UR") => Movement::UpRight(parse_line_number(lmatched)),
+        lmatched if lmatched.starts_with("DL") => Movement::DownLeft(parse_line_number(lmatched)),
+        lmatched if lmatched.starts_with("DR") => Movement::DownRight(parse_line_number(lmatched)),
         lmatched if lmatched.starts_with("U") => Movement::Up(parse_line_number(lmatched)),
         lmatched if lmatched.starts_with("D") => Movement::Down(parse_line_number(lmatched)),
         lmatched if lmatched.starts_with("L") => Movement::Left(parse_line_number(lmatched)),
@@ -119,3 +350,96 @@ fn line_to_movent(line: &str) -> Movement {
 fn parse_line_number(line: &str) -> usize {
     line.split(' ').nth(1).unwrap().parse().unwrap()
 }
+
+#[cfg(test)]
+mod _tests {
+    use super::{
+        line_to_movent, populate_movemt, render_trail, simulate_rope, simulate_rope_segments, trace_to_csv,
+        Movement, Pos,
+    };
+
+    fn parse(input: &str) -> Vec<Movement> {
+        input.lines().map(line_to_movent).collect()
+    }
+
+    #[test]
+    fn test_populate_movemt() {
+        let movements = parse("R 4\nU 4\nL 3\nD 1\nR 4\nD 1\nL 5\nR 2");
+        assert_eq!(populate_movemt(&movements, 2).len(), 13);
+        assert_eq!(populate_movemt(&movements, 10).len(), 1);
+        let movements = parse("R 5\nU 8\nL 8\nD 3\nR 17\nD 10\nL 25\nU 20");
+        assert_eq!(populate_movemt(&movements, 10).len(), 36);
+    }
+
+    #[test]
+    fn test_every_knot_visited() {
+        let movements = parse("R 4\nU 4\nL 3\nD 1\nR 4\nD 1\nL 5\nR 2");
+        let simulation = simulate_rope(&movements, 3, false);
+        assert_eq!(simulation.visited[0].len(), 21);
+        assert_eq!(simulation.visited[1].len(), 13);
+        assert_eq!(simulation.positions[0], (2, 2));
+    }
+
+    #[test]
+    fn test_diagonal_moves() {
+        let simulation = simulate_rope(&parse("UR 3\nDL 1"), 2, true);
+        assert_eq!(simulation.positions, vec![(2, 2), (2, 2)]);
+        assert_eq!(simulation.trace.len(), 5);
+        assert_eq!(simulation.trace[2], vec![(2, 2), (1, 1)]);
+        let mut tail: Vec<Pos> = simulation.visited[1].iter().copied().collect();
+        tail.sort();
+        assert_eq!(tail, vec![(0, 0), (1, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn test_trace_to_csv() {
+        let simulation = simulate_rope(&parse("R 2"), 2, true);
+        assert_eq!(
+            trace_to_csv(&simulation.trace),
+            "step,knot0_x,knot0_y,knot1_x,knot1_y\n0,0,0,0,0\n1,1,0,0,0\n2,2,0,1,0\n"
+        );
+    }
+
+    #[test]
+    fn test_render_trail() {
+        let movements = parse("R 4\nU 4\nL 3\nD 1\nR 4\nD 1\nL 5\nR 2");
+        let simulation = simulate_rope(&movements, 2, false);
+        assert_eq!(
+            render_trail(&simulation.visited[1]),
+            "..##.\n...##\n.####\n....#\ns###."
+        );
+    }
+
+    #[test]
+    fn test_segments_match_step_by_step() {
+        let inputs = [
+            "R 4\nU 4\nL 3\nD 1\nR 4\nD 1\nL 5\nR 2",
+            "R 5\nU 8\nL 8\nD 3\nR 17\nD 10\nL 25\nU 20",
+            "R 30\nUL 7\nD 40\nL 12\nDR 9\nU 25\nR 3\nL 60\nU 2\nR 60",
+        ];
+        let mut state: u64 = 7;
+        let mut generated = String::new();
+        for _ in 0..300 {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let dir = ["U", "D", "L", "R", "UL", "UR", "DL", "DR"][(state >> 60) as usize % 8];
+            generated.push_str(&format!("{} {}\n", dir, (state >> 33) % 25 + 1));
+        }
+        for input in inputs.iter().copied().chain([generated.trim_end()]) {
+            let movements = parse(input);
+            for rope_len in [1, 2, 3, 10] {
+                let expected = simulate_rope(&movements, rope_len, false);
+                let trails = simulate_rope_segments(&movements, rope_len);
+                for (knot, trail) in trails.iter().enumerate() {
+                    assert_eq!(trail.len(), expected.visited[knot].len() as u64, "rope {} knot {}", rope_len, knot);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_segments_huge_moves() {
+        let trails = simulate_rope_segments(&parse("R 100000000\nU 100000000\nL 100000000\nD 99999990"), 10);
+        // same shortfall as the step by step simulation gives for any long enough sides
+        assert_eq!(trails[9].len(), 4 * 100000000 - 45);
+    }
+}
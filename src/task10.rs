@@ -1,83 +1,164 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
 
-use clap::{value_parser, ArgMatches, Command};
+use clap::{builder::RangedU64ValueParser, value_parser, Arg, ArgAction, ArgMatches, Command};
 
-use crate::common;
+use crate::{common, task10ocr};
 
-enum Operation{
+/// Registers are named a..z, the CRT follows x
+const REGISTERS: usize = 26;
+const X: usize = (b'x' - b'a') as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Source {
+    Register(usize),
+    Value(i32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operation {
     Noop,
     Addx(i32),
-    Unknown
+    Set(usize, Source),
+    Add(usize, Source),
+    Sub(usize, Source),
+    Mul(usize, Source),
+    Jmp(usize),
+    Jz(usize, usize),
+    Jnz(usize, usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OperandKind {
+    Register,
+    Source,
+    Value,
+    Label,
+}
+
+struct InstructionSpec {
+    mnemonic: &'static str,
+    operands: &'static [OperandKind],
+    cycles: u32,
+}
+
+const INSTRUCTION_SET: [InstructionSpec; 9] = [
+    InstructionSpec { mnemonic: "noop", operands: &[], cycles: 1 },
+    InstructionSpec { mnemonic: "addx", operands: &[OperandKind::Value], cycles: 2 },
+    InstructionSpec { mnemonic: "set", operands: &[OperandKind::Register, OperandKind::Source], cycles: 1 },
+    InstructionSpec { mnemonic: "add", operands: &[OperandKind::Register, OperandKind::Source], cycles: 2 },
+    InstructionSpec { mnemonic: "sub", operands: &[OperandKind::Register, OperandKind::Source], cycles: 2 },
+    InstructionSpec { mnemonic: "mul", operands: &[OperandKind::Register, OperandKind::Source], cycles: 3 },
+    InstructionSpec { mnemonic: "jmp", operands: &[OperandKind::Label], cycles: 1 },
+    InstructionSpec { mnemonic: "jz", operands: &[OperandKind::Register, OperandKind::Label], cycles: 1 },
+    InstructionSpec { mnemonic: "jnz", operands: &[OperandKind::Register, OperandKind::Label], cycles: 1 },
+];
+
+impl Operation {
+    fn spec(&self) -> &'static InstructionSpec {
+        let index = match self {
+            Operation::Noop => 0,
+            Operation::Addx(_) => 1,
+            Operation::Set(..) => 2,
+            Operation::Add(..) => 3,
+            Operation::Sub(..) => 4,
+            Operation::Mul(..) => 5,
+            Operation::Jmp(_) => 6,
+            Operation::Jz(..) => 7,
+            Operation::Jnz(..) => 8,
+        };
+        &INSTRUCTION_SET[index]
+    }
+
+    fn cycles(&self) -> u32 {
+        self.spec().cycles
+    }
 }
 
 struct ProcessorSim{
-    acc_during: i32,
-    acc_after: i32,
+    registers_during: [i32; REGISTERS],
+    registers_after: [i32; REGISTERS],
     operations: Vec<Operation>,
     current_cycle: u32,
     instruction_pos: usize,
-    //processed_op: &Operation,
     wait_timer: u32,
 }
 
 impl ProcessorSim {
     pub fn new(operations: Vec<Operation>) -> Self {
+        let mut registers = [0; REGISTERS];
+        registers[X] = 1;
         Self { 
-            acc_during: 1,
-            acc_after: 1,
+            registers_during: registers,
+            registers_after: registers,
             operations,
             current_cycle: 0,
             instruction_pos:0,
-            //processed_op:&Operation::Noop,
             wait_timer: 0,
         }
     }
     pub fn pass_cycle(&mut self){
         self.current_cycle += 1; //increase cycle count
-        self.acc_during = self.acc_after;
+        self.registers_during = self.registers_after;
 
         // --- Reading phase
         //check if should draw next operation
         if self.wait_timer == 0 { //if yes, draw it
-            self.wait_timer = Self::op_to_wait(&self.operations[self.instruction_pos])
+            self.wait_timer = self.operations[self.instruction_pos].cycles() - 1;
         }
         else { //if no, wait
             self.wait_timer -= 1;
         }
         // --- Execution phase
         if self.wait_timer == 0 {
-            match self.operations[self.instruction_pos] {
-                Operation::Noop  => {
-                    //do nothing
-                },
-                Operation::Addx(val)  => {
-                    self.acc_after += val;
-                },
-                Operation::Unknown => panic!()
-            }
-            self.instruction_pos += 1;
+            self.instruction_pos = self.execute(self.operations[self.instruction_pos]);
         }
     }
 
+    /// Applies operation to registers, returns position of the next one
+    fn execute(&mut self, operation: Operation) -> usize {
+        let next = self.instruction_pos + 1;
+        let regs = &mut self.registers_after;
+        let read = |regs: &[i32; REGISTERS], source: Source| match source {
+            Source::Register(r) => regs[r],
+            Source::Value(v) => v,
+        };
+        // registers wrap around like a real 32 bit cpu would
+        match operation {
+            Operation::Noop => {}
+            Operation::Addx(val) => regs[X] = regs[X].wrapping_add(val),
+            Operation::Set(r, source) => regs[r] = read(regs, source),
+            Operation::Add(r, source) => regs[r] = regs[r].wrapping_add(read(regs, source)),
+            Operation::Sub(r, source) => regs[r] = regs[r].wrapping_sub(read(regs, source)),
+            Operation::Mul(r, source) => regs[r] = regs[r].wrapping_mul(read(regs, source)),
+            Operation::Jmp(target) => return target,
+            Operation::Jz(r, target) if regs[r] == 0 => return target,
+            Operation::Jnz(r, target) if regs[r] != 0 => return target,
+            Operation::Jz(..) | Operation::Jnz(..) => {}
+        }
+        next
+    }
+
+    /// Value of x during the current cycle
+    pub fn acc_during(&self) -> i32 {
+        self.registers_during[X]
+    }
+
     pub fn signal_strength(&self) -> i128{
-        self.acc_during as i128*self.current_cycle as i128
+        self.acc_during() as i128*self.current_cycle as i128
     }
 
     pub fn is_exhausted(&self) -> bool{
-        self.instruction_pos == self.operations.len() && self.wait_timer == 0
+        self.instruction_pos >= self.operations.len() && self.wait_timer == 0
     }
 
-    fn op_to_wait(op: &Operation) -> u32{
-        match op {
-            Operation::Noop  => 0,
-            Operation::Addx(_)  => 1,
-            Operation::Unknown => panic!()
-        }
-    }
-    pub fn get_pixel(&self) -> char{
-        let position_cursor = ((self.current_cycle-1) % 40) as i32;
-        let position_sprite_start = self.acc_during - 1;
-        let position_sprite_end = self.acc_during + 1;
+    pub fn get_pixel(&self, crt: &Crt) -> char{
+        let position_cursor = ((self.current_cycle-1) % crt.width) as i64;
+        let position_sprite_start = self.acc_during() as i64 - (crt.sprite_width as i64 - 1) / 2;
+        let position_sprite_end = position_sprite_start + crt.sprite_width as i64 - 1;
         if position_cursor >= position_sprite_start && position_cursor <= position_sprite_end{
             '#'
         }
@@ -89,6 +170,63 @@ impl ProcessorSim {
 
 }
 
+/// Display size in pixels, the sprite is centered on x
+pub struct Crt {
+    width: u32,
+    height: u32,
+    sprite_width: u32,
+}
+
+/// Cycles at which signal strength is sampled
+#[derive(Debug, Clone, PartialEq)]
+pub enum Probe {
+    At(u32),
+    Every { step: u32, start: u32, until: Option<u32> },
+}
+
+impl Probe {
+    fn matches(&self, cycle: u32) -> bool {
+        match *self {
+            Probe::At(at) => cycle == at,
+            Probe::Every { step, start, until } => {
+                cycle >= start && (cycle - start).is_multiple_of(step) && until.is_none_or(|until| cycle <= until)
+            }
+        }
+    }
+}
+
+/// Parses `N`, `every N`, `every N starting at M` and either of the latter
+/// followed by `until K`
+fn parse_probe(expr: &str) -> Result<Probe, String> {
+    let number = |word: Option<&str>| -> Result<u32, String> {
+        let word = word.ok_or_else(|| format!("Probe '{}' ends too early", expr))?;
+        word.parse().map_err(|_| format!("'{}' is not a cycle number", word))
+    };
+    let words: Vec<&str> = expr.split_whitespace().collect();
+    let mut words = words.iter().copied().peekable();
+    if words.peek() != Some(&"every") {
+        let cycle = number(words.next())?;
+        return match words.next() {
+            None => Ok(Probe::At(cycle)),
+            Some(word) => Err(format!("Unexpected '{}' in probe '{}'", word, expr)),
+        };
+    }
+    words.next();
+    let step = number(words.next())?;
+    if step == 0 {
+        return Err(String::from("Probe step has to be positive"));
+    }
+    let (mut start, mut until) = (step, None);
+    while let Some(word) = words.next() {
+        match (word, words.next()) {
+            ("starting", Some("at")) => start = number(words.next())?,
+            ("until", next) => until = Some(number(next)?),
+            _ => return Err(format!("Unexpected '{}' in probe '{}'", word, expr)),
+        }
+    }
+    Ok(Probe::Every { step, start, until })
+}
+
 pub fn cli() -> Command {
     Command::new("day10")
         .about("Elvish processor cycles")
@@ -97,48 +235,206 @@ pub fn cli() -> Command {
                 .required(true)
                 .value_parser(value_parser!(std::path::PathBuf)),
         )
+        .arg(
+            Arg::new("max_cycles")
+                .long("max-cycles")
+                .help("Stop programs that did not finish after this many cycles")
+                .default_value("1000000")
+                .value_parser(value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("width")
+                .long("width")
+                .help("CRT width in pixels")
+                .default_value("40")
+                .value_parser(RangedU64ValueParser::<u32>::new().range(1..)),
+        )
+        .arg(
+            Arg::new("height")
+                .long("height")
+                .help("CRT height in pixels, rows past it start a new frame")
+                .default_value("6")
+                .value_parser(RangedU64ValueParser::<u32>::new().range(1..)),
+        )
+        .arg(
+            Arg::new("sprite")
+                .long("sprite")
+                .help("Sprite width in pixels")
+                .default_value("3")
+                .value_parser(RangedU64ValueParser::<u32>::new().range(1..)),
+        )
+        .arg(
+            Arg::new("probe")
+                .short('p')
+                .long("probe")
+                .help("Cycles to sum signal strength at, e.g. 'every 40 starting at 20 until 220'")
+                .action(ArgAction::Append)
+                .default_value("every 40 starting at 20 until 220")
+                .value_parser(parse_probe),
+        )
+        .arg(
+            Arg::new("history")
+                .long("history")
+                .help("Write cycle, x during and x after for every cycle as CSV")
+                .value_parser(value_parser!(std::path::PathBuf)),
+        )
 }
 
 pub fn handle(matches: &ArgMatches) {
     let path = matches.get_one::<std::path::PathBuf>("path");
-    solve(path.unwrap().to_path_buf());
+    let max_cycles = *matches.get_one::<u32>("max_cycles").unwrap();
+    let crt = Crt {
+        width: *matches.get_one::<u32>("width").unwrap(),
+        height: *matches.get_one::<u32>("height").unwrap(),
+        sprite_width: *matches.get_one::<u32>("sprite").unwrap(),
+    };
+    let probes: Vec<Probe> = matches.get_many::<Probe>("probe").unwrap().cloned().collect();
+    let history = matches.get_one::<PathBuf>("history").map(|p| p.as_path());
+    solve(path.unwrap().to_path_buf(), max_cycles, &crt, &probes, history);
 }
 
-pub fn solve(filepath: PathBuf) {
+pub fn solve(filepath: PathBuf, max_cycles: u32, crt: &Crt, probes: &[Probe], history_path: Option<&Path>) {
     if let Ok(lines) = common::read_lines(filepath) {
-        let operations: Vec<Operation> = lines.map(|line| line_to_operation(&line.unwrap())).collect();
+        let lines: Vec<String> = lines.map(|line| line.unwrap()).collect();
+        let operations = match assemble(&lines) {
+            Ok(operations) => operations,
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        };
         let mut simulator = ProcessorSim::new(operations);
-        let cycles_of_intrest:Vec<u32> = vec![20,60,100,140,180,220];
+        let mut history: Vec<(u32, i32, i32)> = Vec::new();
         let mut acc: i128 = 0;
         let mut str_acc = String::new();
+        let mut crt_rows: Vec<String> = Vec::new();
         while !simulator.is_exhausted() {
+            if simulator.current_cycle == max_cycles {
+                eprintln!("Program still running after {} cycles, stopping", max_cycles);
+                break;
+            }
             simulator.pass_cycle();
-            str_acc.push(simulator.get_pixel());
-            if str_acc.len() >=40{
+            if history_path.is_some() {
+                history.push((simulator.current_cycle, simulator.acc_during(), simulator.registers_after[X]));
+            }
+            str_acc.push(simulator.get_pixel(crt));
+            if str_acc.len() >= crt.width as usize{
+                if !crt_rows.is_empty() && crt_rows.len().is_multiple_of(crt.height as usize) {
+                    println!();
+                }
                 println!("B{}E", str_acc);
-                str_acc = String::new();
+                crt_rows.push(std::mem::take(&mut str_acc));
             }
 
-            if cycles_of_intrest.contains(&(simulator.current_cycle)){
-                //println!("State in cycle {}: {} strength({})", simulator.current_cycle, simulator.acc_during, simulator.signal_strength());
+            if probes.iter().any(|probe| probe.matches(simulator.current_cycle)){
+                //println!("State in cycle {}: {} strength({})", simulator.current_cycle, simulator.acc_during(), simulator.signal_strength());
                 acc += simulator.signal_strength();
             }
         }
         println!("Total signal strength: {}", acc);
+        if crt.height as usize == task10ocr::GLYPH_HEIGHT {
+            for frame in crt_rows.chunks_exact(crt.height as usize) {
+                match task10ocr::decode(frame) {
+                    Ok(letters) => println!("CRT shows: {}", letters),
+                    Err(err) => eprintln!("{}", err),
+                }
+            }
+        } else if !crt_rows.is_empty() {
+            println!("Letters are only read off {} pixel high frames", task10ocr::GLYPH_HEIGHT);
+        }
+        if let Some(path) = history_path {
+            if let Err(err) = fs::write(path, history_to_csv(&history)) {
+                eprintln!("Could not write {}: {}", path.display(), err);
+            }
+        }
     }
 }
 
-fn line_to_operation(line: &str) -> Operation{
-    match line{
+fn history_to_csv(history: &[(u32, i32, i32)]) -> String {
+    let mut csv = String::from("cycle,x_during,x_after\n");
+    for (cycle, during, after) in history {
+        csv.push_str(&format!("{},{},{}\n", cycle, during, after));
+    }
+    csv
+}
+
+/// Two pass assembly, labels are `name:` lines, `;` starts a comment
+fn assemble<S: AsRef<str>>(lines: &[S]) -> Result<Vec<Operation>, String> {
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+    let mut statements: Vec<(usize, &str)> = Vec::new();
+    for (line_no, line) in lines.iter().enumerate() {
+        let code = line.as_ref().split(';').next().unwrap().trim();
+        if code.is_empty() {
+            continue;
+        }
+        if let Some(label) = code.strip_suffix(':') {
+            if labels.insert(label.trim(), statements.len()).is_some() {
+                return Err(format!("Line {}: label '{}' defined twice", line_no + 1, label.trim()));
+            }
+        } else {
+            statements.push((line_no, code));
+        }
+    }
+    statements
+        .into_iter()
+        .map(|(line_no, code)| parse_statement(code, &labels).map_err(|err| format!("Line {}: {}", line_no + 1, err)))
+        .collect()
+}
+
+fn parse_statement(code: &str, labels: &HashMap<&str, usize>) -> Result<Operation, String> {
+    let mut words = code.split_whitespace();
+    let mnemonic = words.next().unwrap_or_default();
+    let args: Vec<&str> = words.collect();
+    let spec = INSTRUCTION_SET
+        .iter()
+        .find(|spec| spec.mnemonic == mnemonic)
+        .ok_or_else(|| format!("unknown instruction '{}'", mnemonic))?;
+    if args.len() != spec.operands.len() {
+        return Err(format!("'{}' takes {} operands, got {}", mnemonic, spec.operands.len(), args.len()));
+    }
+
+    let mut registers = Vec::new();
+    let mut sources = Vec::new();
+    let mut targets = Vec::new();
+    for (arg, kind) in args.iter().zip(spec.operands) {
+        match kind {
+            OperandKind::Register => registers.push(parse_register(arg).ok_or_else(|| format!("'{}' is not a register", arg))?),
+            OperandKind::Value => sources.push(Source::Value(arg.parse().map_err(|_| format!("'{}' is not a number", arg))?)),
+            OperandKind::Source => sources.push(match parse_register(arg) {
+                Some(r) => Source::Register(r),
+                None => Source::Value(arg.parse().map_err(|_| format!("'{}' is neither register nor number", arg))?),
+            }),
+            OperandKind::Label => targets.push(*labels.get(arg).ok_or_else(|| format!("unknown label '{}'", arg))?),
+        }
+    }
+
+    Ok(match mnemonic {
         "noop" => Operation::Noop,
-        lmatched if lmatched.starts_with("addx ") => Operation::Addx(line.split(' ').nth(1).unwrap().parse().unwrap()),
-        _ => Operation::Unknown
+        "addx" => match sources[0] {
+            Source::Value(v) => Operation::Addx(v),
+            Source::Register(_) => unreachable!(),
+        },
+        "set" => Operation::Set(registers[0], sources[0]),
+        "add" => Operation::Add(registers[0], sources[0]),
+        "sub" => Operation::Sub(registers[0], sources[0]),
+        "mul" => Operation::Mul(registers[0], sources[0]),
+        "jmp" => Operation::Jmp(targets[0]),
+        "jz" => Operation::Jz(registers[0], targets[0]),
+        "jnz" => Operation::Jnz(registers[0], targets[0]),
+        _ => unreachable!(),
+    })
+}
+
+fn parse_register(word: &str) -> Option<usize> {
+    match word.as_bytes() {
+        [letter @ b'a'..=b'z'] => Some((letter - b'a') as usize),
+        _ => None,
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Operation, ProcessorSim, line_to_operation};
+    use super::{assemble, history_to_csv, parse_probe, Crt, Operation, ProcessorSim, Probe, Source, X};
 
     #[test]
     fn test_cycles(){
@@ -146,24 +442,24 @@ mod tests {
         let mut simulator = ProcessorSim::new(ops);
 
         simulator.pass_cycle();
-        assert_eq!(simulator.acc_during, 1);
-        assert_eq!(simulator.acc_after, 1);
+        assert_eq!(simulator.acc_during(), 1);
+        assert_eq!(simulator.registers_after[X], 1);
         assert_eq!(simulator.current_cycle, 1);
         simulator.pass_cycle();
-        assert_eq!(simulator.acc_during, 1);
-        assert_eq!(simulator.acc_after, 1);
+        assert_eq!(simulator.acc_during(), 1);
+        assert_eq!(simulator.registers_after[X], 1);
         assert_eq!(simulator.current_cycle, 2);
         simulator.pass_cycle();
-        assert_eq!(simulator.acc_during, 1);
-        assert_eq!(simulator.acc_after, 4);
+        assert_eq!(simulator.acc_during(), 1);
+        assert_eq!(simulator.registers_after[X], 4);
         assert_eq!(simulator.current_cycle, 3);
         simulator.pass_cycle();
-        assert_eq!(simulator.acc_during, 4);
-        assert_eq!(simulator.acc_after, 4);
+        assert_eq!(simulator.acc_during(), 4);
+        assert_eq!(simulator.registers_after[X], 4);
         assert_eq!(simulator.current_cycle, 4);
         simulator.pass_cycle();
-        assert_eq!(simulator.acc_during, 4);
-        assert_eq!(simulator.acc_after, -1);
+        assert_eq!(simulator.acc_during(), 4);
+        assert_eq!(simulator.registers_after[X], -1);
         assert_eq!(simulator.current_cycle, 5);
 
     }
@@ -183,16 +479,113 @@ mod tests {
             "addx -1",
             "addx 5"
         );
-        let ops = lines.iter().map(|l| line_to_operation(l)).collect();
+        let ops = assemble(&lines).unwrap();
         let mut simulator = ProcessorSim::new(ops);
         while !simulator.is_exhausted() {
             simulator.pass_cycle();
             if simulator.current_cycle == 20{
-                assert_eq!(simulator.acc_during, 21);
+                assert_eq!(simulator.acc_during(), 21);
                 assert_eq!(simulator.signal_strength(), 420);
             }
         }
 
     }
-}
 
+    #[test]
+    fn test_instruction_costs() {
+        let ops = assemble(&["set a 3", "mul a 4", "add x a", "noop"]).unwrap();
+        assert_eq!(ops[0], Operation::Set(0, Source::Value(3)));
+        assert_eq!(ops[2], Operation::Add(X, Source::Register(0)));
+        let mut simulator = ProcessorSim::new(ops);
+        let mut cycles = 0;
+        while !simulator.is_exhausted() {
+            simulator.pass_cycle();
+            cycles += 1;
+        }
+        assert_eq!(cycles, 1 + 3 + 2 + 1);
+        assert_eq!(simulator.registers_after[X], 13);
+    }
+
+    #[test]
+    fn test_register_overflow() {
+        let program = ["set x 2147483647", "addx 1", "set a -2147483648", "sub a 1", "set b 65536", "mul b 65536", "noop"];
+        let mut simulator = ProcessorSim::new(assemble(&program).unwrap());
+        let crt = Crt { width: 40, height: 6, sprite_width: 3 };
+        while !simulator.is_exhausted() {
+            simulator.pass_cycle();
+            simulator.get_pixel(&crt);
+        }
+        assert_eq!(simulator.registers_after[X], i32::MIN);
+        assert_eq!(simulator.registers_after[0], i32::MAX);
+        assert_eq!(simulator.registers_after[1], 0);
+    }
+
+    #[test]
+    fn test_labels_and_jumps() {
+        let program = [
+            "; count c down, adding 2 to x each time",
+            "    set c 5",
+            "loop:",
+            "    jz c done",
+            "    addx 2",
+            "    sub c 1",
+            "    jmp loop",
+            "done:",
+            "",
+        ];
+        let mut simulator = ProcessorSim::new(assemble(&program).unwrap());
+        while !simulator.is_exhausted() {
+            simulator.pass_cycle();
+        }
+        assert_eq!(simulator.registers_after[X], 11);
+        assert_eq!(simulator.registers_after[2], 0);
+        // set + 5 * (jz + addx + sub + jmp) + final jz
+        assert_eq!(simulator.current_cycle, 1 + 5 * (1 + 2 + 2 + 1) + 1);
+    }
+
+    #[test]
+    fn test_assembly_errors() {
+        assert_eq!(assemble(&["noop", "jump x"]).unwrap_err(), "Line 2: unknown instruction 'jump'");
+        assert_eq!(assemble(&["addx"]).unwrap_err(), "Line 1: 'addx' takes 1 operands, got 0");
+        assert_eq!(assemble(&["addx y"]).unwrap_err(), "Line 1: 'y' is not a number");
+        assert_eq!(assemble(&["jmp nowhere"]).unwrap_err(), "Line 1: unknown label 'nowhere'");
+        assert_eq!(assemble(&["a:", "a:"]).unwrap_err(), "Line 2: label 'a' defined twice");
+    }
+
+    #[test]
+    fn test_probes() {
+        let default = parse_probe("every 40 starting at 20 until 220").unwrap();
+        let cycles: Vec<u32> = (0..400).filter(|&c| default.matches(c)).collect();
+        assert_eq!(cycles, vec![20, 60, 100, 140, 180, 220]);
+        assert_eq!(parse_probe(" 17 "), Ok(Probe::At(17)));
+        assert_eq!(parse_probe("every 5"), Ok(Probe::Every { step: 5, start: 5, until: None }));
+        assert_eq!(
+            parse_probe("every  5 until 30 starting at 2"),
+            Ok(Probe::Every { step: 5, start: 2, until: Some(30) })
+        );
+        assert!(parse_probe("every 0").is_err());
+        assert!(parse_probe("every 40 starting 20").is_err());
+        assert!(parse_probe("20 40").is_err());
+        assert!(parse_probe("every").is_err());
+    }
+
+    #[test]
+    fn test_geometry_and_history() {
+        let mut simulator = ProcessorSim::new(assemble(&["addx 4", "noop", "noop", "noop"]).unwrap());
+        let wide = Crt { width: 10, height: 1, sprite_width: 5 };
+        let narrow = Crt { width: 10, height: 1, sprite_width: 1 };
+        let mut wide_row = String::new();
+        let mut narrow_row = String::new();
+        let mut history = Vec::new();
+        while !simulator.is_exhausted() {
+            simulator.pass_cycle();
+            wide_row.push(simulator.get_pixel(&wide));
+            narrow_row.push(simulator.get_pixel(&narrow));
+            history.push((simulator.current_cycle, simulator.acc_during(), simulator.registers_after[X]));
+        }
+        // x is 1 for two cycles, then 5 and the wide sprite covers 3..=7
+        assert_eq!(wide_row, "## ##");
+        assert_eq!(narrow_row, " #   ");
+        assert_eq!(history_to_csv(&history), "cycle,x_during,x_after\n1,1,1\n2,1,5\n3,5,5\n4,5,5\n5,5,5\n");
+    }
+}
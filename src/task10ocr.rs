@@ -0,0 +1,205 @@
+use std::fmt;
+
+pub const GLYPH_WIDTH: usize = 4;
+pub const GLYPH_HEIGHT: usize = 6;
+/// Letters are separated by one blank column
+const CELL_WIDTH: usize = GLYPH_WIDTH + 1;
+
+/// Letters seen in puzzle outputs, '#' is a lit pixel. Y is five pixels
+/// wide, its last column falls in the gap between letters and is not read.
+const GLYPHS: [(char, [&str; GLYPH_HEIGHT]); 18] = [
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('I', ["###.", ".#..", ".#..", ".#..", ".#..", "###."]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Y', ["#...", "#...", ".#.#", "..#.", "..#.", "..#."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"]),
+];
+
+/// Glyph pixels packed row by row, top left pixel is the highest bit
+type Pattern = u32;
+
+#[derive(Debug, PartialEq)]
+pub struct UnknownGlyph {
+    pub index: usize,
+    pattern: Pattern,
+    /// Nearest known letter and number of pixels that differ
+    pub closest: (char, u32),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum OcrError {
+    WrongHeight(usize),
+    UnknownGlyphs { partial: String, glyphs: Vec<UnknownGlyph> },
+}
+
+impl fmt::Display for OcrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OcrError::WrongHeight(rows) => write!(f, "Expected {} rows of pixels, got {}", GLYPH_HEIGHT, rows),
+            OcrError::UnknownGlyphs { partial, glyphs } => {
+                write!(f, "Could not read all letters: {}", partial)?;
+                for glyph in glyphs {
+                    write!(
+                        f,
+                        "\nLetter {} (columns {}-{}), closest is '{}' with {} pixels off:",
+                        glyph.index + 1,
+                        glyph.index * CELL_WIDTH,
+                        glyph.index * CELL_WIDTH + GLYPH_WIDTH - 1,
+                        glyph.closest.0,
+                        glyph.closest.1
+                    )?;
+                    for row in render_pattern(glyph.pattern) {
+                        write!(f, "\n  {}", row)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Reads letters from CRT rows, any char other than '#' is a dark pixel and
+/// cells without any lit pixel read as spaces. Unknown letters are reported
+/// along with what was read, '?' in their place.
+pub fn decode<S: AsRef<str>>(rows: &[S]) -> Result<String, OcrError> {
+    if rows.len() != GLYPH_HEIGHT {
+        return Err(OcrError::WrongHeight(rows.len()));
+    }
+    let pixels: Vec<Vec<bool>> = rows.iter().map(|row| row.as_ref().chars().map(|c| c == '#').collect()).collect();
+    let width = pixels.iter().map(|row| row.len()).max().unwrap_or(0);
+
+    let mut partial = String::new();
+    let mut glyphs = Vec::new();
+    for index in 0..width.div_ceil(CELL_WIDTH) {
+        let mut pattern: Pattern = 0;
+        for row in &pixels {
+            for col in index * CELL_WIDTH..index * CELL_WIDTH + GLYPH_WIDTH {
+                pattern = pattern << 1 | *row.get(col).unwrap_or(&false) as Pattern;
+            }
+        }
+        if pattern == 0 {
+            partial.push(' ');
+            continue;
+        }
+        let closest = GLYPHS
+            .iter()
+            .map(|(letter, shape)| (*letter, (parse_pattern(shape) ^ pattern).count_ones()))
+            .min_by_key(|&(_, distance)| distance)
+            .unwrap();
+        if closest.1 == 0 {
+            partial.push(closest.0);
+        } else {
+            partial.push('?');
+            glyphs.push(UnknownGlyph { index, pattern, closest });
+        }
+    }
+
+    if glyphs.is_empty() {
+        Ok(partial)
+    } else {
+        Err(OcrError::UnknownGlyphs { partial, glyphs })
+    }
+}
+
+fn parse_pattern(shape: &[&str; GLYPH_HEIGHT]) -> Pattern {
+    shape
+        .iter()
+        .flat_map(|row| row.chars())
+        .fold(0, |pattern, c| pattern << 1 | (c == '#') as Pattern)
+}
+
+fn render_pattern(pattern: Pattern) -> Vec<String> {
+    (0..GLYPH_HEIGHT)
+        .map(|row| {
+            (0..GLYPH_WIDTH)
+                .map(|col| {
+                    let bit = (GLYPH_HEIGHT - row) * GLYPH_WIDTH - col - 1;
+                    if pattern >> bit & 1 == 1 { '#' } else { '.' }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod _tests {
+    use super::{decode, OcrError, CELL_WIDTH, GLYPHS, GLYPH_HEIGHT};
+
+    fn render(text: &str) -> Vec<String> {
+        (0..GLYPH_HEIGHT)
+            .map(|row| {
+                text.chars()
+                    .map(|letter| {
+                        let (_, shape) = GLYPHS.iter().find(|(l, _)| *l == letter).unwrap();
+                        format!("{} ", shape[row].replace('.', " "))
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_decode_alphabet() {
+        let alphabet: String = GLYPHS.iter().map(|(letter, _)| *letter).collect();
+        assert_eq!(decode(&render(&alphabet)), Ok(alphabet));
+    }
+
+    #[test]
+    fn test_decode_crt_rows() {
+        // part 2 answer as the CRT draws it, 40 columns wide
+        let rows = render("PZGPKPEB");
+        assert!(rows.iter().all(|row| row.len() == 8 * CELL_WIDTH));
+        assert_eq!(decode(&rows).unwrap(), "PZGPKPEB");
+        let short: Vec<String> = render("OK").iter().map(|row| format!("{:<20}", row)).collect();
+        assert_eq!(decode(&short).unwrap(), "OK  ");
+    }
+
+    #[test]
+    fn test_decode_i_and_y() {
+        // Y as the screen draws it, reaching into the gap before the next letter
+        let mut rows = render("IYA");
+        for (row, last) in rows.iter_mut().zip(["#", "#", " ", " ", " ", " "]) {
+            row.replace_range(9..10, last);
+        }
+        assert_eq!(rows[0], "###  #   # ##  ");
+        assert_eq!(decode(&rows).unwrap(), "IYA");
+    }
+
+    #[test]
+    fn test_unknown_glyph() {
+        let mut rows = render("HEL");
+        // turn the L into something close to it
+        rows[0].replace_range(10..11, " ");
+        let err = decode(&rows).unwrap_err();
+        match &err {
+            OcrError::UnknownGlyphs { partial, glyphs } => {
+                assert_eq!(partial, "HE?");
+                assert_eq!(glyphs.len(), 1);
+                assert_eq!(glyphs[0].index, 2);
+                assert_eq!(glyphs[0].closest, ('L', 1));
+            }
+            _ => panic!("unexpected error {:?}", err),
+        }
+        let message = err.to_string();
+        assert!(message.starts_with("Could not read all letters: HE?\nLetter 3 (columns 10-13), closest is 'L' with 1 pixels off:"));
+        assert!(message.ends_with("\n  ....\n  #...\n  #...\n  #...\n  #...\n  ####"));
+    }
+
+    #[test]
+    fn test_wrong_height() {
+        assert_eq!(decode(&["#"]), Err(OcrError::WrongHeight(1)));
+    }
+}
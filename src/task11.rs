@@ -1,21 +1,202 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
 
-use clap::{value_parser, ArgMatches, Command};
+use clap::{value_parser, Arg, ArgMatches, Command};
 
 // use rust_decimal::prelude::*;
 // use rust_decimal_macros::dec;
 
 use crate::common;
 
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+/// Right hand side of `new = <expr>`
 #[derive(Eq, PartialEq, Debug, Clone)]
 enum ApeOperation {
-    Multiply(u128),
-    Add(u128),
-    Square,
+    Old,
+    Constant(u128),
+    Apply(Box<ApeOperation>, Operator, Box<ApeOperation>),
+}
+
+impl ApeOperation {
+    /// Evaluates the expression, None on overflow, negative result or division by zero.
+    /// With a modulus, + - and * are done modulo it, so intermediate values stay small.
+    fn eval(&self, old: u128, modulus: Option<u128>) -> Option<u128> {
+        match modulus {
+            Some(m) => self.eval_modular(old, m),
+            // intermediate results may go below zero as long as the final one does not
+            None => u128::try_from(self.eval_signed(i128::try_from(old).ok()?)?).ok(),
+        }
+    }
+
+    fn eval_modular(&self, old: u128, m: u128) -> Option<u128> {
+        match self {
+            ApeOperation::Old => Some(old),
+            ApeOperation::Constant(val) => Some(*val),
+            ApeOperation::Apply(lhs, operator, rhs) => {
+                let (a, b) = (lhs.eval_modular(old, m)?, rhs.eval_modular(old, m)?);
+                match operator {
+                    Operator::Add => Some((a % m + b % m) % m),
+                    Operator::Subtract => Some((a % m + m - b % m) % m),
+                    Operator::Multiply => (a % m).checked_mul(b % m).map(|val| val % m),
+                    Operator::Divide => a.checked_div(b),
+                    Operator::Remainder => a.checked_rem(b),
+                }
+            }
+        }
+    }
+
+    fn eval_signed(&self, old: i128) -> Option<i128> {
+        match self {
+            ApeOperation::Old => Some(old),
+            ApeOperation::Constant(val) => i128::try_from(*val).ok(),
+            ApeOperation::Apply(lhs, operator, rhs) => {
+                let (a, b) = (lhs.eval_signed(old)?, rhs.eval_signed(old)?);
+                match operator {
+                    Operator::Add => a.checked_add(b),
+                    Operator::Subtract => a.checked_sub(b),
+                    Operator::Multiply => a.checked_mul(b),
+                    Operator::Divide => a.checked_div(b),
+                    Operator::Remainder => a.checked_rem(b),
+                }
+            }
+        }
+    }
+
+    /// Whether results stay correct when values are kept modulo the divisors
+    fn is_modular(&self) -> bool {
+        match self {
+            ApeOperation::Old | ApeOperation::Constant(_) => true,
+            ApeOperation::Apply(lhs, operator, rhs) => {
+                !matches!(operator, Operator::Divide | Operator::Remainder) && lhs.is_modular() && rhs.is_modular()
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Old,
+    Number(u128),
+    Operator(Operator),
+    Open,
+    Close,
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '0'..='9' => {
+                let mut number = String::new();
+                while let Some(&digit) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                    number.push(digit);
+                    chars.next();
+                }
+                tokens.push(Token::Number(number.parse().map_err(|_| format!("Number {} is too large", number))?));
+            }
+            'a'..='z' => {
+                let mut word = String::new();
+                while let Some(&letter) = chars.peek().filter(|l| l.is_ascii_alphabetic()) {
+                    word.push(letter);
+                    chars.next();
+                }
+                if word != "old" {
+                    return Err(format!("Unknown name '{}', only 'old' can be used", word));
+                }
+                tokens.push(Token::Old);
+            }
+            _ => {
+                tokens.push(match c {
+                    '+' => Token::Operator(Operator::Add),
+                    '-' => Token::Operator(Operator::Subtract),
+                    '*' => Token::Operator(Operator::Multiply),
+                    '/' => Token::Operator(Operator::Divide),
+                    '%' => Token::Operator(Operator::Remainder),
+                    '(' => Token::Open,
+                    ')' => Token::Close,
+                    _ => return Err(format!("Unexpected '{}' in '{}'", c, expr)),
+                });
+                chars.next();
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parses an expression over `old` and constants, * / % bind tighter than + -
+fn parse_operation(expr: &str) -> Result<ApeOperation, String> {
+    let tokens = tokenize(expr)?;
+    let mut pos = 0;
+    let operation = parse_sum(&tokens, &mut pos)?;
+    match tokens.get(pos) {
+        None => Ok(operation),
+        Some(token) => Err(format!("Unexpected {:?} in '{}'", token, expr)),
+    }
+}
+
+fn parse_sum(tokens: &[Token], pos: &mut usize) -> Result<ApeOperation, String> {
+    let mut lhs = parse_product(tokens, pos)?;
+    while let Some(Token::Operator(operator @ (Operator::Add | Operator::Subtract))) = tokens.get(*pos) {
+        *pos += 1;
+        lhs = ApeOperation::Apply(Box::new(lhs), *operator, Box::new(parse_product(tokens, pos)?));
+    }
+    Ok(lhs)
+}
+
+fn parse_product(tokens: &[Token], pos: &mut usize) -> Result<ApeOperation, String> {
+    let mut lhs = parse_factor(tokens, pos)?;
+    while let Some(Token::Operator(operator @ (Operator::Multiply | Operator::Divide | Operator::Remainder))) =
+        tokens.get(*pos)
+    {
+        *pos += 1;
+        lhs = ApeOperation::Apply(Box::new(lhs), *operator, Box::new(parse_factor(tokens, pos)?));
+    }
+    Ok(lhs)
+}
+
+fn parse_factor(tokens: &[Token], pos: &mut usize) -> Result<ApeOperation, String> {
+    *pos += 1;
+    match tokens.get(*pos - 1) {
+        Some(Token::Old) => Ok(ApeOperation::Old),
+        Some(Token::Number(val)) => Ok(ApeOperation::Constant(*val)),
+        Some(Token::Open) => {
+            let inner = parse_sum(tokens, pos)?;
+            if tokens.get(*pos) != Some(&Token::Close) {
+                return Err(String::from("Missing closing parenthesis"));
+            }
+            *pos += 1;
+            Ok(inner)
+        }
+        Some(token) => Err(format!("Expected value, found {:?}", token)),
+        None => Err(String::from("Expression ends too early")),
+    }
+}
+
+/// Worry level of an item, id stays the same across throws
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+struct Item {
+    id: usize,
+    worry: u128,
 }
+
 #[derive(Eq, PartialEq, Debug, Clone)]
 struct Ape {
-    items: Vec<u128>,
+    items: Vec<Item>,
     operation: ApeOperation,
     div_test: u128,
     tgt_succ: usize,
@@ -31,6 +212,8 @@ impl Ape {
         tgt_succ: usize,
         tgt_fail: usize,
     ) -> Self {
+        // ids are only unique within this monkey until parse_apes numbers all items
+        let items = items.into_iter().enumerate().map(|(id, worry)| Item { id, worry }).collect();
         Self {
             items,
             operation,
@@ -40,23 +223,14 @@ impl Ape {
             inspection_count: 0,
         }
     }
-    pub fn run_ape_logic(&mut self, universal_divisor: Option<u128>) -> Vec<(u128, usize)> {
-        let mut thrown_items: Vec<(u128, usize)> = Vec::new();
+    pub fn run_ape_logic(&mut self, universal_divisor: Option<u128>) -> Result<Vec<(Item, usize)>, String> {
+        let mut thrown_items: Vec<(Item, usize)> = Vec::new();
         for og_item in &self.items {
-            let mut item = *og_item;
             self.inspection_count += 1;
             //increase item level
-            match self.operation {
-                ApeOperation::Multiply(val) => {
-                    item *= val;
-                }
-                ApeOperation::Add(val) => {
-                    item += val;
-                }
-                ApeOperation::Square => {
-                    item *= item;
-                }
-            }
+            let Some(mut item) = self.operation.eval(og_item.worry, universal_divisor) else {
+                return Err(format!("operation gives no valid worry level for old = {}", og_item.worry));
+            };
 
             match universal_divisor {
                 None => {
@@ -70,61 +244,351 @@ impl Ape {
             }
 
             //monke decides where to throw
-            if (item % self.div_test) == 0 {
+            let item = Item { id: og_item.id, worry: item };
+            if item.worry.is_multiple_of(self.div_test) {
                 thrown_items.push((item, self.tgt_succ))
             } else {
                 thrown_items.push((item, self.tgt_fail))
             }
         }
         self.items.clear();
-        thrown_items
+        Ok(thrown_items)
     }
-    pub fn add_item(&mut self, item: u128) {
+    pub fn add_item(&mut self, item: Item) {
         self.items.push(item);
     }
 }
 
+/// One throw in an item's journey, worry is the level after inspection
+struct Throw {
+    round: usize,
+    from: usize,
+    to: usize,
+    worry: u128,
+}
+
+struct Journey {
+    start: usize,
+    initial_worry: u128,
+    throws: Vec<Throw>,
+}
+
+/// Inspections per round and monkey and every throw of every item
+struct Report {
+    monkeys: Vec<usize>,
+    inspections: Vec<Vec<u64>>,
+    journeys: BTreeMap<usize, Journey>,
+}
+
+impl Report {
+    fn new(ape_map: &HashMap<usize, Ape>, ape_indicies: &[usize]) -> Self {
+        let mut journeys = BTreeMap::new();
+        for ape_index in ape_indicies {
+            for item in &ape_map[ape_index].items {
+                journeys.insert(item.id, Journey { start: *ape_index, initial_worry: item.worry, throws: Vec::new() });
+            }
+        }
+        Self { monkeys: ape_indicies.to_vec(), inspections: Vec::new(), journeys }
+    }
+
+    fn inspections_to_csv(&self) -> String {
+        let mut csv = String::from("round,monkey,inspections\n");
+        for (round, counts) in self.inspections.iter().enumerate() {
+            for (monkey, count) in self.monkeys.iter().zip(counts) {
+                csv.push_str(&format!("{},{},{}\n", round + 1, monkey, count));
+            }
+        }
+        csv
+    }
+
+    fn inspections_to_json(&self) -> serde_json::Value {
+        let rounds: Vec<serde_json::Value> = self
+            .inspections
+            .iter()
+            .enumerate()
+            .map(|(round, counts)| serde_json::json!({ "round": round + 1, "inspections": counts }))
+            .collect();
+        serde_json::json!({ "monkeys": self.monkeys, "rounds": rounds })
+    }
+
+    fn journeys_to_csv(&self) -> String {
+        let mut csv = String::from("item,round,from,to,worry\n");
+        for (id, journey) in &self.journeys {
+            csv.push_str(&format!("{},0,,{},{}\n", id, journey.start, journey.initial_worry));
+            for throw in &journey.throws {
+                csv.push_str(&format!("{},{},{},{},{}\n", id, throw.round, throw.from, throw.to, throw.worry));
+            }
+        }
+        csv
+    }
+
+    fn journeys_to_json(&self) -> serde_json::Value {
+        let items: Vec<serde_json::Value> = self
+            .journeys
+            .iter()
+            .map(|(id, journey)| {
+                let throws: Vec<serde_json::Value> = journey
+                    .throws
+                    .iter()
+                    .map(|throw| {
+                        serde_json::json!({
+                            "round": throw.round,
+                            "from": throw.from,
+                            "to": throw.to,
+                            "worry": worry_to_json(throw.worry),
+                        })
+                    })
+                    .collect();
+                serde_json::json!({
+                    "item": id,
+                    "start": journey.start,
+                    "initial_worry": worry_to_json(journey.initial_worry),
+                    "throws": throws,
+                })
+            })
+            .collect();
+        serde_json::Value::from(items)
+    }
+}
+
+/// JSON numbers cannot hold every u128, larger levels are written as strings
+fn worry_to_json(worry: u128) -> serde_json::Value {
+    u64::try_from(worry).map(serde_json::Value::from).unwrap_or_else(|_| serde_json::Value::from(worry.to_string()))
+}
+
 pub fn cli() -> Command {
-    Command::new("day11").about("Sudden ape attack").arg(
-        clap::arg!(path: <PATH>)
-            .required(true)
-            .value_parser(value_parser!(std::path::PathBuf)),
-    )
+    Command::new("day11")
+        .about("Sudden ape attack")
+        .arg(
+            clap::arg!(path: <PATH>)
+                .required(true)
+                .value_parser(value_parser!(std::path::PathBuf)),
+        )
+        .arg(
+            Arg::new("part")
+                .long("part")
+                .help("Which run the reports describe")
+                .default_value("2")
+                .value_parser(["1", "2"]),
+        )
+        .arg(
+            Arg::new("extrapolate")
+                .long("extrapolate")
+                .help("Also count inspections for this many part 2 rounds by finding each item's cycle")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("inspections")
+                .long("inspections")
+                .help("Write inspections per round and monkey, JSON if the name ends with .json, CSV otherwise")
+                .value_parser(value_parser!(std::path::PathBuf)),
+        )
+        .arg(
+            Arg::new("journeys")
+                .long("journeys")
+                .help("Write every throw of every item, JSON if the name ends with .json, CSV otherwise")
+                .value_parser(value_parser!(std::path::PathBuf)),
+        )
 }
 
 pub fn handle(matches: &ArgMatches) {
     let path = matches.get_one::<std::path::PathBuf>("path");
-    solve(path.unwrap().to_path_buf());
+    let report_part = matches.get_one::<String>("part").unwrap() == "1";
+    let inspections = matches.get_one::<PathBuf>("inspections").map(|p| p.as_path());
+    let journeys = matches.get_one::<PathBuf>("journeys").map(|p| p.as_path());
+    let extrapolate = matches.get_one::<u64>("extrapolate").copied();
+    solve(path.unwrap().to_path_buf(), report_part, inspections, journeys, extrapolate);
+}
+
+/// Runs given number of rounds, monkeys take turns in order of ape_indicies
+fn run_rounds(
+    ape_map: &mut HashMap<usize, Ape>,
+    ape_indicies: &[usize],
+    rounds: usize,
+    universal_divisor: Option<u128>,
+    mut report: Option<&mut Report>,
+) -> Result<(), String> {
+    for round in 1..=rounds {
+        let counts_before: Vec<u64> = ape_indicies.iter().map(|i| ape_map[i].inspection_count).collect();
+        for ape_index in ape_indicies {
+            let thrown_items = ape_map
+                .get_mut(ape_index)
+                .unwrap()
+                .run_ape_logic(universal_divisor)
+                .map_err(|err| format!("Monkey {} in round {}: {}", ape_index, round, err))?;
+            for (item, item_target) in &thrown_items {
+                if let Some(report) = report.as_deref_mut() {
+                    let throw = Throw { round, from: *ape_index, to: *item_target, worry: item.worry };
+                    report.journeys.get_mut(&item.id).unwrap().throws.push(throw);
+                }
+                ape_map.get_mut(item_target).unwrap().add_item(*item)
+            }
+        }
+        if let Some(report) = report.as_deref_mut() {
+            let counts = ape_indicies.iter().zip(counts_before).map(|(i, before)| ape_map[i].inspection_count - before);
+            report.inspections.push(counts.collect());
+        }
+    }
+    Ok(())
+}
+
+/// Follows single items under part 2 rules. Items never affect each other,
+/// so each one is a walk over (holder, worry) states taken between rounds.
+struct ItemWalker<'a> {
+    apes: Vec<&'a Ape>,
+    ape_indicies: Vec<usize>,
+    /// Positions in turn order of the monkeys thrown to on success and failure
+    targets: Vec<(usize, usize)>,
+    universal_divisor: u128,
 }
 
-pub fn solve(filepath: PathBuf) {
+type ItemState = (usize, u128);
+
+impl<'a> ItemWalker<'a> {
+    fn new(ape_map: &'a HashMap<usize, Ape>, ape_indicies: &[usize], universal_divisor: u128) -> Self {
+        let position = |ape_no: usize| ape_indicies.iter().position(|&i| i == ape_no).unwrap();
+        let apes: Vec<&Ape> = ape_indicies.iter().map(|i| &ape_map[i]).collect();
+        let targets = apes.iter().map(|ape| (position(ape.tgt_succ), position(ape.tgt_fail))).collect();
+        Self { apes, ape_indicies: ape_indicies.to_vec(), targets, universal_divisor }
+    }
+
+    /// Plays one round for an item, an item thrown to a monkey later in turn
+    /// order gets inspected again in the same round
+    fn round(&self, state: ItemState, counts: &mut [u128]) -> Result<ItemState, String> {
+        let (mut pos, mut worry) = state;
+        loop {
+            let ape = self.apes[pos];
+            counts[pos] += 1;
+            worry = ape.operation.eval(worry, Some(self.universal_divisor)).ok_or_else(|| {
+                format!("Monkey {}: operation gives no valid worry level for old = {}", self.ape_indicies[pos], worry)
+            })? % self.universal_divisor;
+            let next = if worry.is_multiple_of(ape.div_test) { self.targets[pos].0 } else { self.targets[pos].1 };
+            let next_round = next <= pos;
+            pos = next;
+            if next_round {
+                return Ok((pos, worry));
+            }
+        }
+    }
+
+    fn rounds(&self, mut state: ItemState, rounds: u64, counts: &mut [u128]) -> Result<ItemState, String> {
+        for _ in 0..rounds {
+            state = self.round(state, counts)?;
+        }
+        Ok(state)
+    }
+
+    /// Brent's cycle detection, returns rounds before the cycle and its length
+    fn find_cycle(&self, start: ItemState) -> Result<(u64, u64), String> {
+        let mut scratch = vec![0; self.apes.len()];
+        let (mut power, mut length) = (1, 1);
+        let mut tortoise = start;
+        let mut hare = self.round(start, &mut scratch)?;
+        while tortoise != hare {
+            if power == length {
+                tortoise = hare;
+                power *= 2;
+                length = 0;
+            }
+            hare = self.round(hare, &mut scratch)?;
+            length += 1;
+        }
+
+        let mut tortoise = start;
+        let mut hare = self.rounds(start, length, &mut scratch)?;
+        let mut lead_in = 0;
+        while tortoise != hare {
+            tortoise = self.round(tortoise, &mut scratch)?;
+            hare = self.round(hare, &mut scratch)?;
+            lead_in += 1;
+        }
+        Ok((lead_in, length))
+    }
+
+    /// Inspections per monkey in turn order after given number of rounds
+    fn count_inspections(&self, rounds: u64) -> Result<Vec<u128>, String> {
+        let mut counts = vec![0; self.apes.len()];
+        for (pos, ape) in self.apes.iter().enumerate() {
+            for item in &ape.items {
+                let start = (pos, item.worry % self.universal_divisor);
+                let (lead_in, length) = self.find_cycle(start)?;
+                if rounds <= lead_in + length {
+                    self.rounds(start, rounds, &mut counts)?;
+                    continue;
+                }
+                let cycle_start = self.rounds(start, lead_in, &mut counts)?;
+                let mut cycle_counts = vec![0; self.apes.len()];
+                self.rounds(cycle_start, length, &mut cycle_counts)?;
+                let (full_cycles, remainder) = ((rounds - lead_in) / length, (rounds - lead_in) % length);
+                for (count, cycle_count) in counts.iter_mut().zip(cycle_counts) {
+                    *count += cycle_count * full_cycles as u128;
+                }
+                self.rounds(cycle_start, remainder, &mut counts)?;
+            }
+        }
+        Ok(counts)
+    }
+}
+
+fn write_report(report: &Report, inspections: Option<&Path>, journeys: Option<&Path>) {
+    let is_json = |path: &Path| path.extension().is_some_and(|ext| ext == "json");
+    let outputs = [
+        inspections.map(|path| {
+            let content = if is_json(path) {
+                serde_json::to_string_pretty(&report.inspections_to_json()).unwrap()
+            } else {
+                report.inspections_to_csv()
+            };
+            (path, content)
+        }),
+        journeys.map(|path| {
+            let content = if is_json(path) {
+                serde_json::to_string_pretty(&report.journeys_to_json()).unwrap()
+            } else {
+                report.journeys_to_csv()
+            };
+            (path, content)
+        }),
+    ];
+    for (path, content) in outputs.into_iter().flatten() {
+        if let Err(err) = fs::write(path, content) {
+            eprintln!("Could not write {}: {}", path.display(), err);
+        }
+    }
+}
+
+pub fn solve(
+    filepath: PathBuf,
+    report_part_one: bool,
+    inspections: Option<&Path>,
+    journeys: Option<&Path>,
+    extrapolate: Option<u64>,
+) {
     let mut ape_map: HashMap<usize, Ape> = HashMap::new();
-    let mut str_acc: Vec<String> = Vec::new();
     let mut ape_indicies: Vec<usize> = Vec::new();
     if let Ok(lines) = common::read_lines(filepath) {
-        for line in lines {
-            if let Ok(line_text) = line {
-                if !line_text.is_empty() {
-                    str_acc.push(line_text);
+        let lines: Vec<String> = lines.map(|line| line.unwrap()).collect();
+        match parse_apes(&lines) {
+            Ok(apes) => {
+                for (ape_no, ape) in apes {
+                    ape_map.insert(ape_no, ape);
+                    ape_indicies.push(ape_no);
                 }
             }
-            if str_acc.len() >= 6 {
-                let (ape, ape_no) = lines_to_ape(&str_acc);
-                ape_map.insert(ape_no, ape);
-                ape_indicies.push(ape_no);
-                str_acc.clear();
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
             }
         }
     }
     ape_indicies.sort();
+    let wants_report = inspections.is_some() || journeys.is_some();
     let ape_map_clone = ape_map.clone();
-    for _ in 0..20 {
-        for ape_index in &ape_indicies {
-            let thrown_items = ape_map.get_mut(ape_index).unwrap().run_ape_logic(None);
-            for (item_value, item_target) in &thrown_items {
-                ape_map.get_mut(item_target).unwrap().add_item(*item_value)
-            }
-        }
+    let mut report = Report::new(&ape_map, &ape_indicies);
+    let report_one = (wants_report && report_part_one).then_some(&mut report);
+    if let Err(err) = run_rounds(&mut ape_map, &ape_indicies, 20, None, report_one) {
+        eprintln!("{}", err);
+        std::process::exit(1);
     }
     let mut ape_levels: Vec<u64> = ape_indicies
         .iter()
@@ -147,17 +611,38 @@ pub fn solve(filepath: PathBuf) {
             universal_divisor *= ape.div_test;
         }
     }
-
-    for _ in 0..10000 {
-        for ape_index in &ape_indicies {
-            let thrown_items = ape_map
-                .get_mut(ape_index)
-                .unwrap()
-                .run_ape_logic(Some(universal_divisor));
-            for (item_value, item_target) in &thrown_items {
-                ape_map.get_mut(item_target).unwrap().add_item(*item_value)
+    // part 2 results would be wrong, not just slow, so refuse to print them
+    if let Some(ape_no) = ape_indicies.iter().find(|i| !ape_map[i].operation.is_modular()) {
+        if wants_report && report_part_one {
+            write_report(&report, inspections, journeys);
+        }
+        eprintln!(
+            "Monkey {}: operations with / or % do not survive keeping values modulo {}, cannot run part 2",
+            ape_no, universal_divisor
+        );
+        std::process::exit(1);
+    }
+    if let Some(rounds) = extrapolate {
+        let walker = ItemWalker::new(&ape_map, &ape_indicies, universal_divisor);
+        let mut ape_levels = match walker.count_inspections(rounds) {
+            Ok(ape_levels) => ape_levels,
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
             }
+        };
+        ape_levels.sort_by(|a, b| b.cmp(a));
+        print!("Ape levels ({} rounds):", rounds);
+        for ape_level in &ape_levels {
+            print!(" {}", ape_level);
         }
+        println!("\nTwo higest multiplied together: {}", ape_levels[0] * ape_levels[1]);
+    }
+
+    let report_two = (wants_report && !report_part_one).then_some(&mut report);
+    if let Err(err) = run_rounds(&mut ape_map, &ape_indicies, 10000, Some(universal_divisor), report_two) {
+        eprintln!("{}", err);
+        std::process::exit(1);
     }
     let mut ape_levels: Vec<u64> = ape_indicies
         .iter()
@@ -172,127 +657,332 @@ pub fn solve(filepath: PathBuf) {
         "\nTwo higest multiplied together: {}",
         ape_levels[0] * ape_levels[1]
     );
+    if wants_report {
+        write_report(&report, inspections, journeys);
+    }
 }
 
-fn lines_to_ape(lines: &Vec<String>) -> (Ape, usize) {
-    let fst_line_no = lines[0].split(' ').nth(1).unwrap();
-    let ape_no: usize = fst_line_no[0..fst_line_no.len() - 1].parse().unwrap();
-    let snd_line_cut = lines[1].split(": ").nth(1).unwrap();
-    let items: Vec<u128> = snd_line_cut
-        .split(", ")
-        .into_iter()
-        .map(|s| s.parse().unwrap())
-        .collect();
-    let operation: ApeOperation = match &lines[2] {
-        trdline
-            if regex::Regex::new(r"^  Operation: new = old \* \d+$")
-                .unwrap()
-                .is_match(trdline) =>
-        {
-            ApeOperation::Multiply(trdline.split(' ').last().unwrap().parse().unwrap())
-        }
-        trdline
-            if regex::Regex::new(r"^  Operation: new = old \+ \d+$")
-                .unwrap()
-                .is_match(trdline) =>
-        {
-            ApeOperation::Add(trdline.split(' ').last().unwrap().parse().unwrap())
+/// Splits input into monkeys at `Monkey N:` headers, blank lines and
+/// indentation do not matter
+fn parse_apes<S: AsRef<str>>(lines: &[S]) -> Result<Vec<(usize, Ape)>, String> {
+    let mut blocks: Vec<Vec<&str>> = Vec::new();
+    for line in lines.iter().map(|line| line.as_ref().trim()).filter(|line| !line.is_empty()) {
+        match blocks.last_mut() {
+            Some(block) if !line.starts_with("Monkey") => block.push(line),
+            None if !line.starts_with("Monkey") => return Err(format!("Expected 'Monkey N:', found '{}'", line)),
+            _ => blocks.push(vec![line]),
         }
-        trdline
-            if regex::Regex::new(r"^  Operation: new = old \* old$")
-                .unwrap()
-                .is_match(trdline) =>
-        {
-            ApeOperation::Square
+    }
+    let mut apes: Vec<(usize, Ape)> = blocks.iter().map(|block| lines_to_ape(block)).collect::<Result<_, _>>()?;
+    for (id, item) in apes.iter_mut().flat_map(|(_, ape)| ape.items.iter_mut()).enumerate() {
+        item.id = id;
+    }
+    for (ape_no, ape) in &apes {
+        for target in [ape.tgt_succ, ape.tgt_fail] {
+            if !apes.iter().any(|(other, _)| *other == target) {
+                return Err(format!("Monkey {} throws to missing monkey {}", ape_no, target));
+            }
         }
-        _ => panic!(),
+    }
+    Ok(apes)
+}
+
+fn lines_to_ape<S: AsRef<str>>(lines: &[S]) -> Result<(usize, Ape), String> {
+    let header = lines[0].as_ref().trim();
+    let ape_no: usize = header
+        .strip_prefix("Monkey")
+        .and_then(|rest| rest.trim().strip_suffix(':'))
+        .and_then(|no| no.trim().parse().ok())
+        .ok_or_else(|| format!("Bad monkey header '{}'", header))?;
+    let context = |err: String| format!("Monkey {}: {}", ape_no, err);
+
+    let mut fields: HashMap<&str, &str> = HashMap::new();
+    for line in &lines[1..] {
+        let (key, value) = line
+            .as_ref()
+            .split_once(':')
+            .ok_or_else(|| context(format!("Line '{}' has no ':'", line.as_ref().trim())))?;
+        fields.insert(key.trim(), value.trim());
+    }
+    let field = |key: &str| fields.get(key).copied().ok_or_else(|| context(format!("Missing '{}'", key)));
+    let last_number = |key: &str| -> Result<u128, String> {
+        let value = field(key)?;
+        value
+            .split_whitespace()
+            .last()
+            .and_then(|word| word.parse().ok())
+            .ok_or_else(|| context(format!("'{}' does not end with a number", value)))
     };
-    let div_test: u128 = lines[3].split(' ').last().unwrap().parse().unwrap();
-    let tgt_succ: usize = lines[4].split(' ').last().unwrap().parse().unwrap();
-    let tgt_fail: usize = lines[5].split(' ').last().unwrap().parse().unwrap();
-    (
-        Ape::new(items, operation, div_test, tgt_succ, tgt_fail),
-        ape_no,
-    )
+
+    let items: Vec<u128> = field("Starting items")?
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().map_err(|_| context(format!("Bad item '{}'", s))))
+        .collect::<Result<_, _>>()?;
+    let operation_text = field("Operation")?;
+    let expr = operation_text
+        .strip_prefix("new")
+        .and_then(|rest| rest.trim_start().strip_prefix('='))
+        .ok_or_else(|| context(format!("Operation '{}' is not 'new = <expr>'", operation_text)))?;
+    let operation = parse_operation(expr).map_err(context)?;
+    let div_test = last_number("Test")?;
+    if div_test == 0 {
+        return Err(context(String::from("Cannot test divisibility by 0")));
+    }
+    let tgt_succ = last_number("If true")? as usize;
+    let tgt_fail = last_number("If false")? as usize;
+    Ok((ape_no, Ape::new(items, operation, div_test, tgt_succ, tgt_fail)))
 }
 
 #[cfg(test)]
 mod tests {
 
-    use crate::task11::{lines_to_ape, Ape, ApeOperation};
+    use std::collections::HashMap;
+
+    use crate::task11::{
+        lines_to_ape, parse_apes, parse_operation, run_rounds, Ape, ApeOperation, ItemWalker, Operator, Report,
+    };
 
     #[test]
     fn test_parse() {
-        let (ape, ape_no) = lines_to_ape(&vec![
+        let (ape_no, ape) = lines_to_ape(&vec![
             String::from("Monkey 0:"),
             String::from("  Starting items: 79, 98"),
             String::from("  Operation: new = old * 19"),
             String::from("  Test: divisible by 23"),
             String::from("      If true: throw to monkey 2"),
             String::from("      If false: throw to monkey 3"),
-        ]);
+        ])
+        .unwrap();
         assert_eq!(ape_no, 0);
         assert_eq!(
             ape,
-            Ape::new(vec![79, 98], ApeOperation::Multiply(19), 23, 2, 3)
+            Ape::new(vec![79, 98], parse_operation("old * 19").unwrap(), 23, 2, 3)
         );
 
-        let (ape, ape_no) = lines_to_ape(&vec![
+        let (ape_no, ape) = lines_to_ape(&vec![
             String::from("Monkey 1:"),
             String::from("  Starting items: 54, 65, 75, 74"),
             String::from("  Operation: new = old + 6"),
             String::from("  Test: divisible by 19"),
             String::from("      If true: throw to monkey 2"),
             String::from("      If false: throw to monkey 0"),
-        ]);
+        ])
+        .unwrap();
         assert_eq!(ape_no, 1);
         assert_eq!(
             ape,
-            Ape::new(vec![54, 65, 75, 74], ApeOperation::Add(6), 19, 2, 0)
+            Ape::new(vec![54, 65, 75, 74], parse_operation("old + 6").unwrap(), 19, 2, 0)
         );
 
-        let (ape, ape_no) = lines_to_ape(&vec![
+        let (ape_no, ape) = lines_to_ape(&vec![
             String::from("Monkey 2:"),
             String::from("  Starting items: 79, 60, 97"),
             String::from("  Operation: new = old * old"),
             String::from("  Test: divisible by 13"),
             String::from("      If true: throw to monkey 1"),
             String::from("      If false: throw to monkey 3"),
-        ]);
+        ])
+        .unwrap();
         assert_eq!(ape_no, 2);
         assert_eq!(
             ape,
-            Ape::new(vec![79, 60, 97], ApeOperation::Square, 13, 1, 3)
+            Ape::new(vec![79, 60, 97], parse_operation("old * old").unwrap(), 13, 1, 3)
         );
 
-        let (ape, ape_no) = lines_to_ape(&vec![
+        let (ape_no, ape) = lines_to_ape(&vec![
             String::from("Monkey 3:"),
             String::from("  Starting items: 74"),
             String::from("  Operation: new = old + 3"),
             String::from("  Test: divisible by 17"),
             String::from("      If true: throw to monkey 0"),
             String::from("      If false: throw to monkey 1"),
-        ]);
+        ])
+        .unwrap();
         assert_eq!(ape_no, 3);
-        assert_eq!(ape, Ape::new(vec![74], ApeOperation::Add(3), 17, 0, 1));
+        assert_eq!(ape, Ape::new(vec![74], parse_operation("old + 3").unwrap(), 17, 0, 1));
     }
     #[test]
     fn test_round() {
         let mut ape_vec = vec![
-            Ape::new(vec![79, 98], ApeOperation::Multiply(19), 23, 2, 3),
-            Ape::new(vec![54, 65, 75, 74], ApeOperation::Add(6), 19, 2, 0),
-            Ape::new(vec![79, 60, 97], ApeOperation::Square, 13, 1, 3),
-            Ape::new(vec![74], ApeOperation::Add(3), 17, 0, 1),
+            Ape::new(vec![79, 98], parse_operation("old * 19").unwrap(), 23, 2, 3),
+            Ape::new(vec![54, 65, 75, 74], parse_operation("old + 6").unwrap(), 19, 2, 0),
+            Ape::new(vec![79, 60, 97], parse_operation("old * old").unwrap(), 13, 1, 3),
+            Ape::new(vec![74], parse_operation("old + 3").unwrap(), 17, 0, 1),
         ];
         for ape_index in 0..ape_vec.len() {
-            let mus = ape_vec[ape_index].run_ape_logic(None);
+            let mus = ape_vec[ape_index].run_ape_logic(None).unwrap();
             for (item, item_tgt) in mus {
                 ape_vec[item_tgt].add_item(item);
             }
         }
-        assert_eq!(ape_vec[0].items, vec![20, 23, 27, 26]);
-        assert_eq!(ape_vec[1].items, vec![2080, 25, 167, 207, 401, 1046]);
+        let worries = |ape: &Ape| ape.items.iter().map(|item| item.worry).collect::<Vec<u128>>();
+        assert_eq!(worries(&ape_vec[0]), vec![20, 23, 27, 26]);
+        assert_eq!(worries(&ape_vec[1]), vec![2080, 25, 167, 207, 401, 1046]);
         assert!(ape_vec[2].items.is_empty());
         assert!(ape_vec[3].items.is_empty());
     }
+
+    #[test]
+    fn test_negative_worry() {
+        let ape_indicies = vec![0, 1];
+        let mut ape_map = HashMap::from([
+            (0, Ape::new(vec![50], parse_operation("old - 100 + old").unwrap(), 2, 1, 1)),
+            (1, Ape::new(vec![5], parse_operation("old - 6").unwrap(), 2, 0, 0)),
+        ]);
+        assert_eq!(
+            run_rounds(&mut ape_map, &ape_indicies, 20, None, None),
+            Err(String::from("Monkey 1 in round 1: operation gives no valid worry level for old = 5"))
+        );
+    }
+
+    #[test]
+    fn test_parse_operation() {
+        let old = || Box::new(ApeOperation::Old);
+        assert_eq!(
+            parse_operation("old * old").unwrap(),
+            ApeOperation::Apply(old(), Operator::Multiply, old())
+        );
+        let operation = parse_operation("  (old+ 3) *2 - old %4 / 2 ").unwrap();
+        // (10 + 3) * 2 - ((10 % 4) / 2)
+        assert_eq!(operation.eval(10, None), Some(25));
+        assert_eq!(parse_operation("old - 2 - 3").unwrap().eval(10, None), Some(5));
+        assert_eq!(parse_operation("100 / (old - 10)").unwrap().eval(10, None), None);
+        assert_eq!(parse_operation("old - 20").unwrap().eval(10, None), None);
+        assert_eq!(parse_operation("old - 20 + old * 2").unwrap().eval(10, None), Some(10));
+        assert_eq!(parse_operation("(3 - old) * (5 - old)").unwrap().eval(10, None), Some(35));
+        assert!(operation.eval(10, None).is_some());
+        assert!(!operation.is_modular());
+        assert!(parse_operation("old * old + 3 - old").unwrap().is_modular());
+
+        assert!(parse_operation("old *").is_err());
+        assert!(parse_operation("(old + 1").is_err());
+        assert!(parse_operation("old + 1)").is_err());
+        assert!(parse_operation("new + 1").is_err());
+        assert!(parse_operation("old ^ 2").is_err());
+    }
+
+    #[test]
+    fn test_modular_eval() {
+        let operation = parse_operation("old * old - 7").unwrap();
+        for old in [0u128, 3, 10, 1000] {
+            assert_eq!(operation.eval(old, Some(13)), Some((old * old + 13 * 13 - 7) % 13));
+        }
+        // would overflow without the modulus
+        let operation = parse_operation("old * old * old").unwrap();
+        assert_eq!(operation.eval(u64::MAX as u128, None), None);
+        assert!(operation.eval(u64::MAX as u128, Some(9699690)).is_some());
+    }
+
+    #[test]
+    fn test_parse_apes_layout() {
+        let lines = [
+            "",
+            "Monkey 0:",
+            "Starting items:   79,98",
+            "",
+            "   Operation: new=old * (old + 1)",
+            "  Test: divisible by 23",
+            "    If true: throw to monkey 1",
+            "    If false: throw to monkey 1",
+            "",
+            "",
+            "Monkey 1:",
+            "  Starting items:",
+            "  Operation: new = 5",
+            "  Test: divisible by 2",
+            "  If false: throw to monkey 0",
+            "  If true: throw to monkey 0",
+        ];
+        let apes = parse_apes(&lines).unwrap();
+        assert_eq!(apes.len(), 2);
+        assert_eq!(apes[0].1, Ape::new(vec![79, 98], parse_operation("old * (old + 1)").unwrap(), 23, 1, 1));
+        assert_eq!(apes[1].1, Ape::new(vec![], ApeOperation::Constant(5), 2, 0, 0));
+
+        let mut broken = lines.to_vec();
+        broken[6] = "    If true: throw to monkey 4";
+        assert_eq!(parse_apes(&broken).unwrap_err(), "Monkey 0 throws to missing monkey 4");
+        broken[6] = "    If true throw to monkey 1";
+        assert_eq!(parse_apes(&broken).unwrap_err(), "Monkey 0: Line 'If true throw to monkey 1' has no ':'");
+        broken[6] = "    If true: throw to monkey 1";
+        broken[4] = "Operation: old * 2";
+        assert!(parse_apes(&broken).unwrap_err().starts_with("Monkey 0: Operation 'old * 2'"));
+    }
+
+    fn sample_lines() -> Vec<String> {
+        let mut lines = Vec::new();
+        for (no, items, operation, div, succ, fail) in [
+            (0, "79, 98", "old * 19", 23, 2, 3),
+            (1, "54, 65, 75, 74", "old + 6", 19, 2, 0),
+            (2, "79, 60, 97", "old * old", 13, 1, 3),
+            (3, "74", "old + 3", 17, 0, 1),
+        ] {
+            lines.push(format!("Monkey {}:", no));
+            lines.push(format!("Starting items: {}", items));
+            lines.push(format!("Operation: new = {}", operation));
+            lines.push(format!("Test: divisible by {}", div));
+            lines.push(format!("If true: throw to monkey {}", succ));
+            lines.push(format!("If false: throw to monkey {}", fail));
+        }
+        lines
+    }
+
+    #[test]
+    fn test_report() {
+        let apes = parse_apes(&sample_lines()).unwrap();
+        let ids: Vec<usize> = apes.iter().flat_map(|(_, ape)| ape.items.iter().map(|item| item.id)).collect();
+        assert_eq!(ids, (0..10).collect::<Vec<usize>>());
+
+        let ape_indicies = vec![0, 1, 2, 3];
+        let mut ape_map: HashMap<usize, Ape> = apes.into_iter().collect();
+        let mut report = Report::new(&ape_map, &ape_indicies);
+        run_rounds(&mut ape_map, &ape_indicies, 20, None, Some(&mut report)).unwrap();
+        assert_eq!(report.inspections[0], vec![2, 4, 3, 5]);
+        let totals: Vec<u64> = (0..4).map(|m| report.inspections.iter().map(|round| round[m]).sum()).collect();
+        assert_eq!(totals, vec![101, 95, 7, 105]);
+        // every inspection is one throw
+        let throws: usize = report.journeys.values().map(|journey| journey.throws.len()).sum();
+        assert_eq!(throws as u64, totals.iter().sum::<u64>());
+
+        let csv = report.journeys_to_csv();
+        assert!(csv.starts_with("item,round,from,to,worry\n0,0,,0,79\n0,1,0,3,500\n0,1,3,1,167\n"));
+        let inspections = report.inspections_to_csv();
+        assert!(inspections.starts_with("round,monkey,inspections\n1,0,2\n1,1,4\n1,2,3\n1,3,5\n2,0,"));
+        let json = report.journeys_to_json();
+        assert_eq!(json[0]["throws"][1]["worry"], 167);
+        assert_eq!(json[9]["start"], 3);
+        let json = report.inspections_to_json();
+        assert_eq!(json["rounds"][0]["inspections"], serde_json::json!([2, 4, 3, 5]));
+    }
+
+    #[test]
+    fn test_extrapolated_inspections() {
+        let ape_indicies = vec![0, 1, 2, 3];
+        let ape_map: HashMap<usize, Ape> = parse_apes(&sample_lines()).unwrap().into_iter().collect();
+        let universal_divisor = 23 * 19 * 13 * 17;
+        let walker = ItemWalker::new(&ape_map, &ape_indicies, universal_divisor);
+        assert_eq!(walker.count_inspections(10000), Ok(vec![52166, 47830, 1938, 52013]));
+
+        for rounds in [1, 20, 1000, 4321] {
+            let mut simulated = ape_map.clone();
+            run_rounds(&mut simulated, &ape_indicies, rounds, Some(universal_divisor), None).unwrap();
+            let expected: Vec<u128> = ape_indicies.iter().map(|i| simulated[i].inspection_count as u128).collect();
+            assert_eq!(walker.count_inspections(rounds as u64), Ok(expected), "{} rounds", rounds);
+        }
+
+        let huge = walker.count_inspections(1_000_000_000_000).unwrap();
+        let total: u128 = huge.iter().sum();
+        // every item is inspected at least once per round
+        assert!(total >= 10 * 1_000_000_000_000);
+        assert!(huge[0] > 5_000_000_000_000);
+
+        // a divisor past 64 bits lets the modular product overflow
+        let ape_map = HashMap::from([(0, Ape::new(vec![1 << 66], parse_operation("old * old").unwrap(), 1 << 70, 0, 0))]);
+        let walker = ItemWalker::new(&ape_map, &[0], 1 << 70);
+        assert_eq!(
+            walker.count_inspections(5),
+            Err(format!("Monkey 0: operation gives no valid worry level for old = {}", 1u128 << 66))
+        );
+    }
 }
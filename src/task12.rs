@@ -1,16 +1,77 @@
 use std::{
-    collections::{HashMap, VecDeque},
-    path::PathBuf,
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
 };
 
-use clap::{value_parser, ArgMatches, Command};
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
 
 use crate::common;
 
+/// Cost to reach a target and next cell towards it
+type Visited = HashMap<(usize, usize), (u64, (usize, usize))>;
+
+/// What moves are allowed and what they cost, heights are in letters
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClimbingRules {
+    max_ascent: i32,
+    /// None lets you jump down any cliff
+    max_descent: Option<i32>,
+    diagonal: bool,
+    step_cost: u64,
+    /// Added for every level climbed
+    ascent_cost: u64,
+    /// Added for every level descended
+    descent_cost: u64,
+}
+
+impl Default for ClimbingRules {
+    fn default() -> Self {
+        Self { max_ascent: 1, max_descent: None, diagonal: false, step_cost: 1, ascent_cost: 0, descent_cost: 0 }
+    }
+}
+
+impl ClimbingRules {
+    fn allows(&self, from_height: i32, to_height: i32) -> bool {
+        let diff = to_height - from_height;
+        diff <= self.max_ascent && self.max_descent.is_none_or(|max| -diff <= max)
+    }
+
+    fn cost(&self, from_height: i32, to_height: i32) -> u64 {
+        let diff = to_height - from_height;
+        self.step_cost + self.ascent_cost * diff.max(0) as u64 + self.descent_cost * (-diff).max(0) as u64
+    }
+
+    /// Every step costs the same, so the fewest steps are also the cheapest
+    fn is_uniform(&self) -> bool {
+        self.step_cost > 0 && self.ascent_cost == 0 && self.descent_cost == 0
+    }
+}
+
+/// Set of cells a query starts or ends in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cells {
+    Start,
+    End,
+    Elevation(i32),
+}
+
+/// Reads `S`, `E` or an elevation letter
+fn parse_cells(text: &str) -> Result<Cells, String> {
+    match text {
+        "S" => Ok(Cells::Start),
+        "E" => Ok(Cells::End),
+        _ if text.len() == 1 && text.as_bytes()[0].is_ascii_lowercase() => Ok(Cells::Elevation(text.as_bytes()[0] as i32)),
+        _ => Err(format!("'{}' is neither S, E nor a letter a-z", text)),
+    }
+}
+
 struct Graph {
     start: (usize, usize),
     end: (usize, usize),
     nodes: HashMap<(usize, usize), i32>,
+    rules: ClimbingRules,
 }
 
 impl Graph {
@@ -19,6 +80,7 @@ impl Graph {
             start: (0, 0),
             end: (0, 0),
             nodes: HashMap::new(),
+            rules: ClimbingRules::default(),
         }
     }
     fn add_edge(&mut self, x: usize, y: usize, val: i32) {
@@ -30,96 +92,299 @@ impl Graph {
     fn set_end(&mut self, x: usize, y: usize) {
         self.end = (x, y)
     }
+    /// Searches run backwards, so this tells whether rules allow moving from `to` to `from`
     fn edge_exists(&self, from: (usize,usize), to:(usize,usize)) -> bool{
-        if from == to{
-            false
+        let (dx, dy) = (from.0.abs_diff(to.0), from.1.abs_diff(to.1));
+        if dx.max(dy) != 1 || (dx == 1 && dy == 1 && !self.rules.diagonal) {
+            return false;
         }
-        else if !self.nodes.contains_key(&to) || !self.nodes.contains_key(&from){
-            false
+        match (self.nodes.get(&from), self.nodes.get(&to)) {
+            (Some(&from_height), Some(&to_height)) => self.rules.allows(to_height, from_height),
+            _ => false,
         }
-        else if (from.0 as i32 - to.0 as i32).abs() == 1 && from.1 == to.1{
-            self.height_diff(from,to) >= -1
-        }
-        else if (from.1 as i32 - to.1 as i32).abs() == 1 && from.0 == to.0{
-            self.height_diff(from,to) >= -1
-        }
-        else{
-            false
+    }
+
+    fn calc_path(&self, to_elev: Option<i32>) -> Option<usize>{
+        self.shortest_path(to_elev).map(|path| path.len() - 1)
+    }
+
+    fn select(&self, cells: Cells) -> Vec<(usize, usize)> {
+        match cells {
+            Cells::Start => vec![self.start],
+            Cells::End => vec![self.end],
+            Cells::Elevation(val) => self.nodes.iter().filter(|(_, &h)| h == val).map(|(&pos, _)| pos).collect(),
         }
     }
-    fn height_diff(&self, from: (usize,usize), to:(usize,usize)) -> i32{
-        self.nodes[&to] - self.nodes[&from]
+
+    /// Shortest path from start, or closest cell with given elevation, to end
+    fn shortest_path(&self, to_elev: Option<i32>) -> Option<Vec<(usize, usize)>> {
+        self.cheapest_path(to_elev.map_or(Cells::Start, Cells::Elevation), Cells::End)
     }
 
-    fn calc_path(&self, to_elev: Option<i32>) -> Option<usize>{
-        let mut distance: HashMap<(usize, usize), usize> = HashMap::new();
-        let mut queue: VecDeque<(usize, usize)> = VecDeque::new();
-        distance.insert(self.end, 0);
-        queue.push_back(self.end);
-        while let Some((x,y)) = queue.pop_front(){
-            let current_len = distance[&(x,y)];
-            //println!("Looking at ({},{}), with distance {}", x,y, current_len);
-            match to_elev{
-                None => {
-                    if (x,y) == self.start{
-                        return Some(current_len);
-                    }
-                }
-                Some(val) =>{
-                    if self.nodes[&(x,y)] == val{
-                        return Some(current_len);
-                    }
-                }
+    /// Cheapest path from any of the sources to any of the targets
+    fn cheapest_path(&self, sources: Cells, targets: Cells) -> Option<Vec<(usize, usize)>> {
+        let sources: HashSet<(usize, usize)> = self.select(sources).into_iter().collect();
+        let (found, visited) = self.search(&self.select(targets), |pos| sources.contains(&pos));
+        let mut path = vec![found?];
+        while let Some(&(_, next)) = visited.get(path.last().unwrap()) {
+            if next == *path.last().unwrap() {
+                break;
             }
-            
-            if self.edge_exists((x,y), (x,y+1)) && !distance.contains_key(&(x,y+1)) {
-                distance.insert((x,y+1), current_len+1);
-                queue.push_back((x,y+1));
+            path.push(next);
+        }
+        Some(path)
+    }
+
+    /// Fails when a path through every cell could cost more than fits in u64,
+    /// below that no step or path cost can overflow
+    fn check_costs(&self) -> Result<(), String> {
+        let span = match (self.nodes.values().min(), self.nodes.values().max()) {
+            (Some(low), Some(high)) => high.abs_diff(*low) as u64,
+            _ => 0,
+        };
+        self.rules
+            .ascent_cost
+            .max(self.rules.descent_cost)
+            .checked_mul(span)
+            .and_then(|cost| cost.checked_add(self.rules.step_cost))
+            .and_then(|cost| cost.checked_mul(self.nodes.len() as u64))
+            .map(|_| ())
+            .ok_or_else(|| format!("Step costs are too large, a path over {} cells could overflow", self.nodes.len()))
+    }
+
+    fn path_cost(&self, path: &[(usize, usize)]) -> u64 {
+        path.windows(2).map(|step| self.rules.cost(self.nodes[&step[0]], self.nodes[&step[1]])).sum()
+    }
+
+    /// Cost of reaching end from every cell that can reach it
+    fn distance_field(&self) -> HashMap<(usize, usize), u64> {
+        let (_, visited) = self.search(&[self.end], |_| false);
+        visited.into_iter().map(|(pos, (distance, _))| (pos, distance)).collect()
+    }
+
+    /// Reverse Dijkstra from all targets until a cell matching is_source is
+    /// settled. Every reached cell maps to its cost and the next cell towards a target.
+    fn search<F: Fn((usize, usize)) -> bool>(&self, targets: &[(usize, usize)], is_source: F) -> (Option<(usize, usize)>, Visited) {
+        let mut visited: Visited = HashMap::new();
+        let mut queue: BinaryHeap<Reverse<(u64, (usize, usize))>> = BinaryHeap::new();
+        for &target in targets {
+            visited.insert(target, (0, target));
+            queue.push(Reverse((0, target)));
+        }
+        while let Some(Reverse((current_cost, (x, y)))) = queue.pop() {
+            if current_cost > visited[&(x, y)].0 {
+                continue;
             }
-            if self.edge_exists((x,y), (x+1,y)) && !distance.contains_key(&(x+1,y)) {
-                distance.insert((x+1,y), current_len+1);
-                queue.push_back((x+1,y));
+            if is_source((x, y)) {
+                return (Some((x, y)), visited);
             }
-            if y!=0 && self.edge_exists((x,y), (x,y-1)) && !distance.contains_key(&(x,y-1)) {
-                distance.insert((x,y-1), current_len+1);
-                queue.push_back((x,y-1));
+
+            for (dx, dy) in [(0, 1), (1, 0), (0, -1), (-1, 0), (1, 1), (1, -1), (-1, 1), (-1, -1)] {
+                let (Some(nx), Some(ny)) = (x.checked_add_signed(dx), y.checked_add_signed(dy)) else {
+                    continue;
+                };
+                if !self.edge_exists((x, y), (nx, ny)) {
+                    continue;
+                }
+                let cost = current_cost + self.rules.cost(self.nodes[&(nx, ny)], self.nodes[&(x, y)]);
+                if visited.get(&(nx, ny)).is_none_or(|&(known, _)| cost < known) {
+                    visited.insert((nx, ny), (cost, (x, y)));
+                    queue.push(Reverse((cost, (nx, ny))));
+                }
             }
-            if x!=0 &&self.edge_exists((x,y), (x-1,y)) && !distance.contains_key(&(x-1,y)) {
-                distance.insert((x-1,y), current_len+1);
-                queue.push_back((x-1,y));
+        }
+        (None, visited)
+    }
+
+    fn size(&self) -> (usize, usize) {
+        let width = self.nodes.keys().map(|&(x, _)| x + 1).max().unwrap_or(0);
+        let height = self.nodes.keys().map(|&(_, y)| y + 1).max().unwrap_or(0);
+        (width, height)
+    }
+}
+
+/// Draws path like the puzzle does, arrows point to the next step and
+/// diagonal steps show as slashes. A path not ending in E ends in '#'.
+fn render_path(graph: &Graph, path: &[(usize, usize)]) -> String {
+    let (width, height) = graph.size();
+    let mut grid = vec![vec!['.'; width]; height];
+    for step in path.windows(2) {
+        let ((x, y), (nx, ny)) = (step[0], step[1]);
+        grid[y][x] = match (nx as isize - x as isize, ny as isize - y as isize) {
+            (1, 0) => '>',
+            (-1, 0) => '<',
+            (0, 1) => 'v',
+            (0, -1) => '^',
+            (1, 1) | (-1, -1) => '\\',
+            _ => '/',
+        };
+    }
+    grid[graph.end.1][graph.end.0] = 'E';
+    if let Some(&(x, y)) = path.last().filter(|&&last| last != graph.end) {
+        grid[y][x] = '#';
+    }
+    grid.iter().map(|row| row.iter().collect::<String>() + "\n").collect()
+}
+
+fn distances_to_csv(graph: &Graph, distances: &HashMap<(usize, usize), u64>) -> String {
+    let (width, height) = graph.size();
+    let mut csv = String::from("x,y,height,distance\n");
+    for y in 0..height {
+        for x in 0..width {
+            if let Some(elevation) = graph.nodes.get(&(x, y)) {
+                let distance = distances.get(&(x, y)).map(|d| d.to_string()).unwrap_or_default();
+                csv.push_str(&format!("{},{},{},{}\n", x, y, (*elevation as u8) as char, distance));
             }
         }
-        None
     }
+    csv
 }
 
 pub fn cli() -> Command {
-    Command::new("day12").about("Elvish hill climbing").arg(
-        clap::arg!(path: <PATH>)
-            .required(true)
-            .value_parser(value_parser!(std::path::PathBuf)),
-    )
+    Command::new("day12")
+        .about("Elvish hill climbing")
+        .arg(
+            clap::arg!(path: <PATH>)
+                .required(true)
+                .value_parser(value_parser!(std::path::PathBuf)),
+        )
+        .arg(
+            Arg::new("draw")
+                .short('d')
+                .long("draw")
+                .help("Draw found paths with arrows")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("distances")
+                .long("distances")
+                .help("Write cost of reaching the end from every cell as CSV, empty when unreachable")
+                .value_parser(value_parser!(std::path::PathBuf)),
+        )
+        .arg(
+            Arg::new("from")
+                .long("from")
+                .help("Cells to start from instead of the two puzzle queries, S, E or an elevation like a")
+                .requires("to")
+                .value_parser(parse_cells),
+        )
+        .arg(
+            Arg::new("to")
+                .long("to")
+                .help("Cells to reach, S, E or an elevation like z")
+                .requires("from")
+                .value_parser(parse_cells),
+        )
+        .arg(
+            Arg::new("max_ascent")
+                .long("max-ascent")
+                .help("Most levels one step may climb")
+                .default_value("1")
+                .value_parser(value_parser!(i32)),
+        )
+        .arg(
+            Arg::new("max_descent")
+                .long("max-descent")
+                .help("Most levels one step may descend, unlimited by default")
+                .value_parser(value_parser!(i32)),
+        )
+        .arg(
+            Arg::new("diagonal")
+                .long("diagonal")
+                .help("Allow diagonal steps")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("step_cost")
+                .long("step-cost")
+                .help("Cost of every step")
+                .default_value("1")
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("ascent_cost")
+                .long("ascent-cost")
+                .help("Extra cost per level climbed")
+                .default_value("0")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("descent_cost")
+                .long("descent-cost")
+                .help("Extra cost per level descended")
+                .default_value("0")
+                .value_parser(value_parser!(u64)),
+        )
 }
 
 pub fn handle(matches: &ArgMatches) {
     let path = matches.get_one::<std::path::PathBuf>("path");
-    solve(path.unwrap().to_path_buf());
+    let distances = matches.get_one::<PathBuf>("distances").map(|p| p.as_path());
+    let rules = ClimbingRules {
+        max_ascent: *matches.get_one::<i32>("max_ascent").unwrap(),
+        max_descent: matches.get_one::<i32>("max_descent").copied(),
+        diagonal: matches.get_flag("diagonal"),
+        step_cost: *matches.get_one::<u64>("step_cost").unwrap(),
+        ascent_cost: *matches.get_one::<u64>("ascent_cost").unwrap(),
+        descent_cost: *matches.get_one::<u64>("descent_cost").unwrap(),
+    };
+    let query = matches.get_one::<Cells>("from").copied().zip(matches.get_one::<Cells>("to").copied());
+    solve(path.unwrap().to_path_buf(), rules, query, matches.get_flag("draw"), distances);
 }
 
-pub fn solve(filepath: PathBuf) {
+pub fn solve(filepath: PathBuf, rules: ClimbingRules, query: Option<(Cells, Cells)>, draw: bool, distances: Option<&Path>) {
     if let Ok(lines) = common::read_lines(filepath) {
-        let graph = parse_lines(lines.map(|l| l.unwrap()));
-        match graph.calc_path(None){
-            Some(x) => {
-                println!("Shortest path to end has {} length", x);
+        let mut graph = parse_lines(lines.map(|l| l.unwrap()));
+        graph.rules = rules;
+        if let Err(err) = graph.check_costs() {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        if let Some((sources, targets)) = query {
+            match graph.cheapest_path(sources, targets) {
+                Some(path) => {
+                    println!("Cheapest path has {} length and costs {}", path.len() - 1, graph.path_cost(&path));
+                    if draw {
+                        print!("{}", render_path(&graph, &path));
+                    }
+                }
+                None => {
+                    println!("No path");
+                }
             }
-            None => {
-                println!("No path");
+        } else {
+            solve_puzzle(&graph, draw);
+        }
+        if let Some(target) = distances {
+            if let Err(err) = fs::write(target, distances_to_csv(&graph, &graph.distance_field())) {
+                eprintln!("Could not write {}: {}", target.display(), err);
+            }
+        }
+    }
+}
+
+fn solve_puzzle(graph: &Graph, draw: bool) {
+    for (name, to_elev) in [("end", None), ("depression", Some('a' as i32))] {
+        if graph.rules.is_uniform() {
+            match graph.calc_path(to_elev) {
+                Some(x) => {
+                    println!("Shortest path to {} has {} length", name, x);
+                    if draw {
+                        print!("{}", render_path(graph, &graph.shortest_path(to_elev).unwrap()));
+                    }
+                }
+                None => {
+                    println!("No path");
+                }
             }
+            continue;
         }
-        match graph.calc_path(Some('a' as i32)){
-            Some(x) => {
-                println!("Shortest path to depression has {} length", x);
+        match graph.shortest_path(to_elev) {
+            Some(path) => {
+                println!("Cheapest path to {} has {} length and costs {}", name, path.len() - 1, graph.path_cost(&path));
+                if draw {
+                    print!("{}", render_path(graph, &path));
+                }
             }
             None => {
                 println!("No path");
@@ -152,7 +417,7 @@ fn parse_lines(lines: impl Iterator<Item = String>) -> Graph {
 
 #[cfg(test)]
 mod tests {
-    use super::parse_lines;
+    use super::{distances_to_csv, parse_cells, parse_lines, render_path, Cells, ClimbingRules};
 
 
     #[test]
@@ -168,4 +433,91 @@ mod tests {
         assert_eq!(graph.calc_path(None), Some(31));
         assert_eq!(graph.calc_path(Some('a' as i32)), Some(29));
     }
+
+    #[test]
+    fn test_path_rendering() {
+        let lines = ["Sabqponm", "abcryxxl", "accszExk", "acctuvwj", "abdefghi"];
+        let graph = parse_lines(lines.iter().map(|l| l.to_string()));
+        let path = graph.shortest_path(None).unwrap();
+        assert_eq!(path.len(), 32);
+        assert_eq!(path[0], (0, 0));
+        assert_eq!(*path.last().unwrap(), (5, 2));
+        assert!(path.windows(2).all(|step| graph.edge_exists(step[1], step[0])));
+        let rendering = render_path(&graph, &path);
+        assert_eq!(rendering.lines().count(), 5);
+        assert_eq!(rendering.matches(['^', 'v', '<', '>']).count(), 31);
+        assert_eq!(rendering.lines().nth(2).unwrap().chars().nth(5), Some('E'));
+    }
+
+    #[test]
+    fn test_distance_field() {
+        // the walled off corner cannot reach the end
+        let lines = ["Sbcz", "aaaz", "zzzE"];
+        let graph = parse_lines(lines.iter().map(|l| l.to_string()));
+        let distances = graph.distance_field();
+        assert_eq!(distances[&(3, 2)], 0);
+        assert_eq!(distances.get(&(0, 0)), None);
+        assert_eq!(graph.calc_path(None), None);
+        let csv = distances_to_csv(&graph, &distances);
+        assert!(csv.starts_with("x,y,height,distance\n0,0,a,\n"));
+        assert!(csv.ends_with("2,2,z,1\n3,2,z,0\n"));
+    }
+
+    #[test]
+    fn test_climbing_rules() {
+        // straight along the top climbs to z and back, the bottom detour climbs steadily
+        let lines = ["SzaE", "ammz"];
+        let mut graph = parse_lines(lines.iter().map(|l| l.to_string()));
+        assert_eq!(graph.calc_path(None), None);
+
+        graph.rules = ClimbingRules { max_ascent: 25, ..ClimbingRules::default() };
+        assert_eq!(graph.calc_path(None), Some(3));
+
+        graph.rules = ClimbingRules { max_ascent: 25, ascent_cost: 10, ..ClimbingRules::default() };
+        let path = graph.shortest_path(None).unwrap();
+        assert_eq!(path, vec![(0, 0), (0, 1), (1, 1), (2, 1), (3, 1), (3, 0)]);
+        assert_eq!(graph.path_cost(&path), 5 + 250);
+
+        graph.rules = ClimbingRules { max_ascent: 25, max_descent: Some(0), ..ClimbingRules::default() };
+        assert_eq!(graph.calc_path(None), Some(5));
+
+        graph.rules = ClimbingRules { max_ascent: 25, ascent_cost: 10, diagonal: true, ..ClimbingRules::default() };
+        let path = graph.shortest_path(None).unwrap();
+        assert_eq!(graph.path_cost(&path), 3 + 250);
+        assert_eq!(render_path(&graph, &path), "\\..E\n.>/.\n");
+        assert_eq!(graph.check_costs(), Ok(()));
+
+        graph.rules = ClimbingRules { step_cost: 0, ..ClimbingRules::default() };
+        assert!(!graph.rules.is_uniform());
+        graph.rules = ClimbingRules { ascent_cost: u64::MAX / 200, ..ClimbingRules::default() };
+        assert_eq!(graph.check_costs(), Ok(()));
+        graph.rules = ClimbingRules { ascent_cost: u64::MAX / 100, ..ClimbingRules::default() };
+        assert_eq!(graph.check_costs(), Err(String::from("Step costs are too large, a path over 8 cells could overflow")));
+    }
+
+    #[test]
+    fn test_multi_queries() {
+        let lines = ["Sabqponm", "abcryxxl", "accszExk", "acctuvwj", "abdefghi"];
+        let mut graph = parse_lines(lines.iter().map(|l| l.to_string()));
+        let every_a = parse_cells("a").unwrap();
+        assert_eq!(every_a, Cells::Elevation('a' as i32));
+        assert_eq!(graph.cheapest_path(every_a, Cells::End).unwrap().len() - 1, 29);
+        assert_eq!(graph.cheapest_path(Cells::Start, Cells::End).unwrap().len() - 1, 31);
+
+        // reaching any z is at least as quick as reaching E, which is a z too
+        let path = graph.cheapest_path(every_a, Cells::Elevation('z' as i32)).unwrap();
+        assert!(path.len() - 1 <= 29);
+        assert_eq!(graph.nodes[&path[0]], 'a' as i32);
+        assert_eq!(graph.nodes[path.last().unwrap()], 'z' as i32);
+        assert_eq!(render_path(&graph, &path).matches('#').count(), (*path.last().unwrap() != graph.end) as usize);
+
+        // diagonal moves can only shorten the climb
+        graph.rules.diagonal = true;
+        let path = graph.shortest_path(None).unwrap();
+        assert!(path.len() - 1 < 31);
+        assert!(path.windows(2).all(|step| graph.edge_exists(step[1], step[0])));
+
+        assert!(parse_cells("Z").is_err());
+        assert!(parse_cells("ab").is_err());
+    }
 }
@@ -1,216 +1,383 @@
 use std::{
+    cmp::Ordering,
+    fmt,
     path::PathBuf,
 };
 
-use clap::{value_parser, ArgMatches, Command};
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
 
 use crate::common;
 
+/// Packet tree, == compares structure while `compare` gives the puzzle's order
 #[derive(PartialEq, Eq, Debug, Clone)]
 enum PacketTree {
     Int(i32),
     List(Vec<PacketTree>),
-    ListBeginsPlaceholder,
 }
 
 impl PacketTree {
-    fn compare_tree_lists(left: &Vec<PacketTree>, right: &Vec<PacketTree>) -> Option<bool> {
-        let mut i: usize = 0;
-        loop{
-            if left.len() <= i && right.len() <= i{
-                //ran out of items, lets leave this recursion
-                return None;
-            }
-            else if left.len() <= i {
-                return Some(true);
-            }
-            else if right.len() <= i {
-                return Some(false);
-            }
-            else{
-                let (litem, ritem) = (&left[i],&right[i]);
-                match (litem, ritem) {
-                    (PacketTree::Int(lval), PacketTree::Int(rval)) if *lval < *rval =>{
-                        //println!("Compare {} vs {}, true", lval, rval);
-                        return Some(true);
-                    }
-                    (PacketTree::Int(lval), PacketTree::Int(rval)) if *lval > *rval =>{
-                        //println!("Compare {} vs {}, false", lval, rval);
-                        return Some(false);
-                    }
-                    (PacketTree::Int(_), PacketTree::Int(_)) => {
-                        //println!("Compare {} vs {}, continue", lval, rval);
-                        //values are same, do nothing, continue iteration
-                    }
-                    (PacketTree::List(llist), PacketTree::List(rlist)) => {
-                        match Self::compare_tree_lists(llist, rlist) {
-                            Some(result) => {
-                                return Some(result);
-                            }
-                            None => {
-                                //do nothing, continue iteration
-                            }
-                        }
-                    }
-                    (PacketTree::Int(lval), PacketTree::List(rlist)) => {
-                        match Self::compare_tree_lists(&vec![PacketTree::Int(*lval)], rlist) {
-                            Some(result) => {
-                                return Some(result);
-                            }
-                            None => {
-                                //do nothing, continue iteration
-                            }
-                        }
-                    }
-                    (PacketTree::List(llist), PacketTree::Int(rval)) => {
-                        match Self::compare_tree_lists(llist, &vec![PacketTree::Int(*rval)]) {
-                            Some(result) => {
-                                return Some(result);
-                            }
-                            None => {
-                                //do nothing, continue iteration
-                            }
-                        }
-                    }
-                    _ => unreachable!()
-                }
-                i += 1;
-            }
+    /// Nesting level, a bare integer has depth 0
+    fn depth(&self) -> usize {
+        match self {
+            PacketTree::Int(_) => 0,
+            PacketTree::List(list) => 1 + list.iter().map(|item| item.depth()).max().unwrap_or(0),
+        }
+    }
+
+    /// All integers in order of appearance
+    fn flatten(&self) -> Vec<i32> {
+        let mut acc = Vec::new();
+        self.flatten_into(&mut acc);
+        acc
+    }
+
+    fn flatten_into(&self, acc: &mut Vec<i32>) {
+        match self {
+            PacketTree::Int(val) => acc.push(*val),
+            PacketTree::List(list) => list.iter().for_each(|item| item.flatten_into(acc)),
         }
     }
+
+    fn sum(&self) -> i64 {
+        match self {
+            PacketTree::Int(val) => *val as i64,
+            PacketTree::List(list) => list.iter().map(|item| item.sum()).sum(),
+        }
+    }
+
+    /// Puzzle order, an integer against a list compares as a one element
+    /// list, so `1` and `[1]` compare Equal while not being ==
+    fn compare(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (PacketTree::Int(lval), PacketTree::Int(rval)) => lval.cmp(rval),
+            (PacketTree::List(llist), PacketTree::List(rlist)) => compare_lists(llist, rlist),
+            (PacketTree::Int(_), PacketTree::List(rlist)) => compare_lists(std::slice::from_ref(self), rlist),
+            (PacketTree::List(llist), PacketTree::Int(_)) => compare_lists(llist, std::slice::from_ref(other)),
+        }
+    }
+}
+
+fn compare_lists(left: &[PacketTree], right: &[PacketTree]) -> Ordering {
+    left.iter()
+        .zip(right)
+        .map(|(l, r)| l.compare(r))
+        .find(|ord| ord.is_ne())
+        .unwrap_or_else(|| left.len().cmp(&right.len()))
 }
 
-impl PartialOrd for PacketTree{
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        match (self,other) {
-            (Self::List(llist), Self::List(rlist)) => {
-                match Self::compare_tree_lists(llist, rlist) {
-                    None => Some(std::cmp::Ordering::Equal),
-                    Some(false) => Some(std::cmp::Ordering::Greater),
-                    Some(true) => Some(std::cmp::Ordering::Less),
+impl fmt::Display for PacketTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PacketTree::Int(val) => write!(f, "{}", val),
+            PacketTree::List(list) => {
+                write!(f, "[")?;
+                for (i, item) in list.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
                 }
+                write!(f, "]")
             }
-            _ => Some(std::cmp::Ordering::Equal)
         }
     }
 }
 
-impl Ord for PacketTree {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+impl From<&PacketTree> for serde_json::Value {
+    fn from(tree: &PacketTree) -> Self {
+        match tree {
+            PacketTree::Int(val) => serde_json::Value::from(*val),
+            PacketTree::List(list) => serde_json::Value::Array(list.iter().map(serde_json::Value::from).collect()),
+        }
     }
 }
 
+impl TryFrom<&serde_json::Value> for PacketTree {
+    type Error = String;
+
+    fn try_from(value: &serde_json::Value) -> Result<Self, Self::Error> {
+        match value {
+            serde_json::Value::Number(number) => number
+                .as_i64()
+                .and_then(|val| i32::try_from(val).ok())
+                .map(PacketTree::Int)
+                .ok_or_else(|| format!("{} is not a 32 bit integer", number)),
+            serde_json::Value::Array(items) => {
+                items.iter().map(PacketTree::try_from).collect::<Result<_, _>>().map(PacketTree::List)
+            }
+            other => Err(format!("Packets hold only integers and lists, found {}", other)),
+        }
+    }
+}
 
 pub fn cli() -> Command {
-    Command::new("day13").about("Elvish distress signal").arg(
-        clap::arg!(path: <PATH>)
-            .required(true)
-            .value_parser(value_parser!(std::path::PathBuf)),
-    )
+    Command::new("day13")
+        .about("Elvish distress signal")
+        .arg(
+            clap::arg!(path: <PATH>)
+                .required(true)
+                .value_parser(value_parser!(std::path::PathBuf)),
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .help("Read packets as JSON values, one per line, whitespace allowed")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("stats")
+                .long("stats")
+                .help("Print depth, integer count and sum over all packets")
+                .action(ArgAction::SetTrue),
+        )
 }
 
 pub fn handle(matches: &ArgMatches) {
     let path = matches.get_one::<std::path::PathBuf>("path");
-    solve(path.unwrap().to_path_buf());
-}
-
-pub fn solve(filepath: PathBuf) {
-    if let Ok(lines) = common::read_lines(&filepath) {
-        let mut counter: usize = 0;
-        let mut packet_tree_vec = parse_lines(lines.map(|l| l.unwrap()));
-        while !packet_tree_vec.is_empty() {
-            let presumed_index = packet_tree_vec.len() / 2;
-            let right = packet_tree_vec.pop().unwrap();
-            let left = packet_tree_vec.pop().unwrap();
-            if let (PacketTree::List(llist), PacketTree::List(rlist)) = (left,right){
-                if PacketTree::compare_tree_lists(&llist, &rlist).unwrap(){
-                    counter += presumed_index;
+    solve(path.unwrap().to_path_buf(), matches.get_flag("json"), matches.get_flag("stats"));
+}
+
+pub fn solve(filepath: PathBuf, json: bool, stats: bool) {
+    let Ok(lines) = common::read_lines(&filepath) else {
+        return;
+    };
+    let lines = lines.map(|l| l.unwrap());
+    let answers = if json {
+        let packets = match parse_json_lines(lines) {
+            Ok(packets) => packets,
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        };
+        if stats {
+            println!("{}", packet_stats(&packets));
+        }
+        order_answers(packets, DIVIDERS.map(|divider| parse_line(divider).unwrap()), |a, b| Ok(a.compare(b)))
+    } else {
+        let packets: Vec<String> = lines.filter(|line| !line.is_empty()).collect();
+        if stats {
+            match parse_lines(packets.iter().cloned()) {
+                Ok(trees) => println!("{}", packet_stats(&trees)),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
                 }
             }
+        }
+        order_answers(packets, DIVIDERS.map(String::from), |a, b| compare_packets(a.as_bytes(), b.as_bytes()))
+    };
+    let (counter, dividers) = match answers {
+        Ok(answers) => answers,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    println!("Counter value: {}", counter);
+    for i in &dividers {
+        println!("index of divider {}", i);
+    }
+    println!("multiplied together {}", dividers.iter().map(|i| i + 1).product::<usize>());
+}
+
+const DIVIDERS: [&str; 2] = ["[[2]]", "[[6]]"];
 
+/// Sum of indices of pairs in the right order and where the dividers end up
+/// once all packets are sorted, counting from 0
+fn order_answers<T, F>(packets: Vec<T>, dividers: [T; 2], compare: F) -> Result<(usize, Vec<usize>), String>
+where
+    F: Fn(&T, &T) -> Result<Ordering, String>,
+{
+    let mut counter: usize = 0;
+    for (i, pair) in packets.chunks_exact(2).enumerate() {
+        if compare(&pair[0], &pair[1]).map_err(|err| format!("Pair {}: {}", i + 1, err))? == Ordering::Less {
+            counter += i + 1;
         }
-        println!("Counter value: {}", counter);
-    }
-    if let Ok(lines) = common::read_lines(&filepath) {
-        let mut bak: usize = 1;
-        let divpacket_2 = PacketTree::List(vec![PacketTree::List(vec![PacketTree::Int(2)])]);
-        let divpacket_6 = PacketTree::List(vec![PacketTree::List(vec![PacketTree::Int(6)])]);
-        let mut packet_tree_vec = parse_lines(lines.map(|l| l.unwrap()));
-        packet_tree_vec.push(divpacket_2.clone());
-        packet_tree_vec.push(divpacket_6.clone());
-        packet_tree_vec.sort();
-        for (i, tree) in packet_tree_vec.into_iter().enumerate(){
-            //println!("{}: {:?}", i, tree);
-            if tree == divpacket_2 || tree == divpacket_6{
-                println!("index of divider {}", i);
-                bak *= i+1;
+    }
+    // tagged, input may hold packets equal to the dividers
+    let mut tagged: Vec<(T, bool)> = packets.into_iter().map(|packet| (packet, false)).collect();
+    tagged.extend(dividers.into_iter().map(|divider| (divider, true)));
+    let mut failure = None;
+    tagged.sort_by(|(a, _), (b, _)| {
+        compare(a, b).unwrap_or_else(|err| {
+            failure.get_or_insert(err);
+            Ordering::Equal
+        })
+    });
+    if let Some(err) = failure {
+        return Err(err);
+    }
+    let positions = tagged.iter().enumerate().filter(|(_, (_, is_divider))| *is_divider).map(|(i, _)| i).collect();
+    Ok((counter, positions))
+}
+
+fn packet_stats(packets: &[PacketTree]) -> String {
+    let deepest = packets.iter().map(|packet| packet.depth()).max().unwrap_or(0);
+    let integers: usize = packets.iter().map(|packet| packet.flatten().len()).sum();
+    let sum: i64 = packets.iter().map(|packet| packet.sum()).sum();
+    format!("Packets: {}, deepest: {}, integers: {}, sum: {}", packets.len(), deepest, integers, sum)
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Int(i64),
+    End,
+}
+
+/// Reads packet text token by token. An integer compared against a list is
+/// pushed back with an extra virtual closing bracket, so promotion needs no
+/// allocation and no copy of the packet.
+struct PacketCursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    pushed_back: Option<i64>,
+    virtual_closes: usize,
+}
+
+impl<'a> PacketCursor<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0, pushed_back: None, virtual_closes: 0 }
+    }
+
+    fn promote(&mut self, val: i64) {
+        self.pushed_back = Some(val);
+        self.virtual_closes += 1;
+    }
+
+    fn next_token(&mut self) -> Result<Token, String> {
+        if let Some(val) = self.pushed_back.take() {
+            return Ok(Token::Int(val));
+        }
+        if self.virtual_closes > 0 {
+            self.virtual_closes -= 1;
+            return Ok(Token::Close);
+        }
+        while let Some(&byte) = self.bytes.get(self.pos) {
+            self.pos += 1;
+            match byte {
+                b'[' => return Ok(Token::Open),
+                b']' => return Ok(Token::Close),
+                b'-' | b'0'..=b'9' => {
+                    let start = self.pos - 1;
+                    let negative = byte == b'-';
+                    // built with the sign applied so i64::MIN still fits
+                    let sign = if negative { -1 } else { 1 };
+                    let mut val = if negative { 0 } else { (byte - b'0') as i64 };
+                    while let Some(digit) = self.bytes.get(self.pos).filter(|b| b.is_ascii_digit()) {
+                        val = val
+                            .checked_mul(10)
+                            .and_then(|val| val.checked_add(sign * (digit - b'0') as i64))
+                            .ok_or_else(|| format!("Integer at byte {} does not fit in 64 bits", start + 1))?;
+                        self.pos += 1;
+                    }
+                    if self.pos == start + 1 && negative {
+                        return Err(format!("Integer at byte {} has no digits", start + 1));
+                    }
+                    if self.bytes.get(self.pos) == Some(&b'-') {
+                        return Err(format!("Unexpected '-' at byte {}", self.pos + 1));
+                    }
+                    return Ok(Token::Int(val));
+                }
+                b',' => {}
+                _ => return Err(format!("Unexpected '{}' at byte {}", byte.escape_ascii(), self.pos)),
             }
         }
-        println!("multiplied together {}", bak);
+        Ok(Token::End)
     }
 }
 
-fn parse_lines(lines: impl Iterator<Item = String>) -> Vec<PacketTree> {
-    let mut trees_acc: Vec<PacketTree> = Vec::new();
-    for line in lines {
-        if !line.is_empty() {
-            trees_acc.push(parse_line(&line))
+/// Compares two packets straight from their text
+fn compare_packets(left: &[u8], right: &[u8]) -> Result<Ordering, String> {
+    let (mut left, mut right) = (PacketCursor::new(left), PacketCursor::new(right));
+    loop {
+        match (left.next_token()?, right.next_token()?) {
+            (Token::Int(lval), Token::Int(rval)) if lval != rval => return Ok(lval.cmp(&rval)),
+            (Token::Int(_), Token::Int(_)) | (Token::Open, Token::Open) | (Token::Close, Token::Close) => {}
+            (Token::End, Token::End) => return Ok(Ordering::Equal),
+            (Token::Close | Token::End, _) => return Ok(Ordering::Less),
+            (_, Token::Close | Token::End) => return Ok(Ordering::Greater),
+            (Token::Int(lval), Token::Open) => left.promote(lval),
+            (Token::Open, Token::Int(rval)) => right.promote(rval),
         }
     }
-    trees_acc
 }
-fn parse_line(line: &str) -> PacketTree {
-    let mut stack: Vec<PacketTree> = Vec::new();
+
+/// Packets from text lines, empty lines are skipped but still counted
+fn parse_lines(lines: impl Iterator<Item = String>) -> Result<Vec<PacketTree>, String> {
+    lines
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(line_no, line)| parse_line(&line).map_err(|err| format!("Line {}: {}", line_no + 1, err)))
+        .collect()
+}
+fn parse_line(line: &str) -> Result<PacketTree, String> {
+    // lists still being read, innermost last
+    let mut stack: Vec<Vec<PacketTree>> = Vec::new();
+    let mut finished: Option<PacketTree> = None;
     let mut parser_acc: String = String::new();
+    let push_int = |stack: &mut Vec<Vec<PacketTree>>, parser_acc: &mut String| -> Result<(), String> {
+        if !parser_acc.is_empty() {
+            let val = parser_acc.parse().map_err(|_| format!("{} is not a 32 bit integer", parser_acc))?;
+            stack.last_mut().ok_or("Integer outside of a list")?.push(PacketTree::Int(val));
+            parser_acc.clear();
+        }
+        Ok(())
+    };
     for ch in line.chars() {
+        if finished.is_some() {
+            return Err(format!("Unexpected '{}' after the packet", ch));
+        }
         match ch {
             '[' => {
-                stack.push(PacketTree::ListBeginsPlaceholder);
+                stack.push(Vec::new());
             }
-            dig if dig.is_numeric() => {
+            '-' | '0'..='9' => {
                 parser_acc.push(ch);
             }
-            ',' => {
-                if !parser_acc.is_empty() {
-                    stack.push(PacketTree::Int(parser_acc.parse().unwrap()));
-                    parser_acc.clear();
-                }
-            }
+            ',' => push_int(&mut stack, &mut parser_acc)?,
             ']' => {
-                if !parser_acc.is_empty() {
-                    stack.push(PacketTree::Int(parser_acc.parse().unwrap()));
-                    parser_acc.clear();
-                }
-                let mut new_vec: Vec<PacketTree> = Vec::new();
-                loop {
-                    match stack.pop().unwrap() {
-                        PacketTree::ListBeginsPlaceholder => {
-                            new_vec.reverse();
-                            stack.push(PacketTree::List(new_vec));
-                            break;
-                        }
-                        list_or_int => {
-                            new_vec.push(list_or_int);
-                        }
-                    }
+                push_int(&mut stack, &mut parser_acc)?;
+                let list = PacketTree::List(stack.pop().ok_or("Unbalanced ']'")?);
+                match stack.last_mut() {
+                    Some(parent) => parent.push(list),
+                    None => finished = Some(list),
                 }
             }
-            _ => unreachable!(),
+            _ => return Err(format!("Unexpected '{}'", ch)),
         }
     }
-    assert!(parser_acc.is_empty() && stack.len() == 1);
-    stack.pop().unwrap()
+    finished.ok_or_else(|| String::from("Packet is not a closed list"))
+}
+
+fn parse_json_lines(lines: impl Iterator<Item = String>) -> Result<Vec<PacketTree>, String> {
+    lines
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(line_no, line)| {
+            serde_json::from_str::<serde_json::Value>(&line)
+                .map_err(|err| err.to_string())
+                .and_then(|value| PacketTree::try_from(&value))
+                .map_err(|err| format!("Line {}: {}", line_no + 1, err))
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{parse_line, PacketTree};
+    use std::cmp::Ordering;
+
+    use super::{
+        cli, compare_packets, handle, order_answers, packet_stats, parse_json_lines, parse_line, parse_lines,
+        PacketTree, DIVIDERS,
+    };
 
     #[test]
     fn test_parse_line() {
         assert_eq!(
-            parse_line("[1,1,3,1,1]"),
+            parse_line("[1,1,3,1,1]").unwrap(),
             PacketTree::List(vec![
                 PacketTree::Int(1),
                 PacketTree::Int(1),
@@ -220,7 +387,7 @@ mod tests {
             ])
         );
         assert_eq!(
-            parse_line("[[1],[2,3,4]]"),
+            parse_line("[[1],[2,3,4]]").unwrap(),
             PacketTree::List(vec![
                 PacketTree::List(vec![PacketTree::Int(1)]),
                 PacketTree::List(vec![
@@ -231,4 +398,197 @@ mod tests {
             ])
         )
     }
+
+    const SAMPLE: [&str; 16] = [
+        "[1,1,3,1,1]",
+        "[1,1,5,1,1]",
+        "[[1],[2,3,4]]",
+        "[[1],4]",
+        "[9]",
+        "[[8,7,6]]",
+        "[[4,4],4,4]",
+        "[[4,4],4,4,4]",
+        "[7,7,7,7]",
+        "[7,7,7]",
+        "[]",
+        "[3]",
+        "[[[]]]",
+        "[[]]",
+        "[1,[2,[3,[4,[5,6,7]]]],8,9]",
+        "[1,[2,[3,[4,[5,6,0]]]],8,9]",
+    ];
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse_line("[-2147483648]").unwrap(), PacketTree::List(vec![PacketTree::Int(i32::MIN)]));
+        assert_eq!(parse_line("[2147483648]").unwrap_err(), "2147483648 is not a 32 bit integer");
+        assert_eq!(parse_line("[1]]").unwrap_err(), "Unexpected ']' after the packet");
+        assert_eq!(parse_line("[[1]").unwrap_err(), "Packet is not a closed list");
+        assert_eq!(parse_line("1").unwrap_err(), "Packet is not a closed list");
+        assert_eq!(parse_line("[a]").unwrap_err(), "Unexpected 'a'");
+        assert_eq!(
+            parse_lines(["[1]", "", "[1-]"].iter().map(|l| l.to_string())).unwrap_err(),
+            "Line 3: 1- is not a 32 bit integer"
+        );
+        assert_eq!(compare_packets(b"[-9223372036854775808]", b"[9223372036854775807]"), Ok(Ordering::Less));
+        assert!(compare_packets(b"[1]", b"[9223372036854775808]").is_err());
+        assert!(compare_packets(b"[-9223372036854775809]", b"[1]").is_err());
+        // the streaming compare rejects what parse_line rejects
+        assert_eq!(parse_line("[ 1]").unwrap_err(), "Unexpected ' '");
+        assert_eq!(compare_packets(b"[ 1]", b"[1]").unwrap_err(), "Unexpected ' ' at byte 2");
+        assert_eq!(compare_packets(b"[1]", b"[\xc3\xa9]").unwrap_err(), "Unexpected '\\xc3' at byte 2");
+        assert!(parse_line("[-]").is_err());
+        assert_eq!(compare_packets(b"[-]", b"[1]").unwrap_err(), "Integer at byte 2 has no digits");
+        assert!(parse_line("[1-2]").is_err());
+        assert_eq!(compare_packets(b"[1-2]", b"[1]").unwrap_err(), "Unexpected '-' at byte 3");
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        for line in SAMPLE {
+            assert_eq!(parse_line(line).unwrap().to_string(), line);
+        }
+        assert_eq!(PacketTree::Int(-3).to_string(), "-3");
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        for line in SAMPLE {
+            let packet = parse_line(line).unwrap();
+            let value = serde_json::Value::from(&packet);
+            assert_eq!(value.to_string(), line);
+            assert_eq!(PacketTree::try_from(&value), Ok(packet));
+        }
+        let packets = parse_json_lines(["[ 1, [2 ,3] ]", "", "  [[ ]]"].iter().map(|l| l.to_string())).unwrap();
+        assert_eq!(packets, vec![parse_line("[1,[2,3]]").unwrap(), parse_line("[[]]").unwrap()]);
+        assert_eq!(
+            parse_json_lines(["[1]", "[1.5]"].iter().map(|l| l.to_string())).unwrap_err(),
+            "Line 2: 1.5 is not a 32 bit integer"
+        );
+        assert!(parse_json_lines(["[\"a\"]"].iter().map(|l| l.to_string())).is_err());
+        assert!(parse_json_lines(["[4294967296]"].iter().map(|l| l.to_string())).is_err());
+    }
+
+    #[test]
+    fn test_ordering() {
+        let packets = parse_lines(SAMPLE.iter().map(|l| l.to_string())).unwrap();
+        let ordered: Vec<usize> = (0..packets.len() / 2).filter(|&i| packets[2 * i].compare(&packets[2 * i + 1]) == Ordering::Less).map(|i| i + 1).collect();
+        assert_eq!(ordered, vec![1, 2, 4, 6]);
+
+        let int = |val| PacketTree::Int(val);
+        let list = |items| PacketTree::List(items);
+        assert_eq!(int(1).compare(&list(vec![int(1)])), Ordering::Equal);
+        assert_eq!(int(2).compare(&list(vec![int(1), int(5)])), Ordering::Greater);
+        assert_eq!(int(1).compare(&list(vec![int(1), int(5)])), Ordering::Less);
+        assert_eq!(list(vec![]).compare(&int(0)), Ordering::Less);
+        assert_eq!(list(vec![list(vec![int(3)])]).compare(&int(3)), Ordering::Equal);
+        assert_eq!(int(4).compare(&int(3)), Ordering::Greater);
+
+        let mut sorted = packets.clone();
+        sorted.push(parse_line("[[2]]").unwrap());
+        sorted.push(parse_line("[[6]]").unwrap());
+        sorted.sort_by(PacketTree::compare);
+        let dividers: Vec<usize> = sorted
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| **p == parse_line("[[2]]").unwrap() || **p == parse_line("[[6]]").unwrap())
+            .map(|(i, _)| i + 1)
+            .collect();
+        assert_eq!(dividers, vec![10, 14]);
+    }
+
+    #[test]
+    fn test_json_stats() {
+        let path = std::env::temp_dir().join(format!("aoc2022_day13_stats_{}.json", std::process::id()));
+        std::fs::write(&path, "[-3, 1]\n[[ -10 ], 4]\n").unwrap();
+        let lines = std::fs::read_to_string(&path).unwrap();
+        let packets = parse_json_lines(lines.lines().map(String::from)).unwrap();
+        assert_eq!(packets, parse_lines(["[-3,1]", "[[-10],4]"].iter().map(|l| l.to_string())).unwrap());
+        assert_eq!(packet_stats(&packets), "Packets: 2, deepest: 2, integers: 4, sum: -8");
+        handle(&cli().get_matches_from(["day13", "--json", "--stats", path.to_str().unwrap()]));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_order_answers() {
+        let packets: Vec<String> = SAMPLE.iter().map(|l| l.to_string()).collect();
+        let trees = parse_lines(packets.iter().cloned()).unwrap();
+        let text = order_answers(packets, DIVIDERS.map(String::from), |a, b| compare_packets(a.as_bytes(), b.as_bytes()));
+        assert_eq!(text, Ok((13, vec![9, 13])));
+        let dividers = DIVIDERS.map(|divider| parse_line(divider).unwrap());
+        assert_eq!(order_answers(trees, dividers, |a, b| Ok(a.compare(b))), text);
+        // packets equal to a divider do not count as one
+        let compare = |a: &&str, b: &&str| compare_packets(a.as_bytes(), b.as_bytes());
+        let (_, dividers) = order_answers(vec!["[[2]]", "[2]", "[[6]]"], DIVIDERS, compare).unwrap();
+        assert_eq!(dividers.len(), 2);
+        assert_eq!(
+            order_answers(vec!["[1]", "[2]", "[3]", "[99999999999999999999]"], DIVIDERS, compare),
+            Err(String::from("Pair 2: Integer at byte 2 does not fit in 64 bits"))
+        );
+        assert!(order_answers(vec!["[[99999999999999999999]]", "[1]", "[2]"], DIVIDERS, compare).is_err());
+    }
+
+    #[test]
+    fn test_packet_operations() {
+        let packet = parse_line("[1,[2,[3,[4,[5,6,7]]]],8,9]").unwrap();
+        assert_eq!(packet.depth(), 5);
+        assert_eq!(packet.flatten(), vec![1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(packet.sum(), 45);
+        let empty = parse_line("[[[]]]").unwrap();
+        assert_eq!(empty.depth(), 3);
+        assert!(empty.flatten().is_empty());
+        assert_eq!(empty.sum(), 0);
+        assert_eq!(PacketTree::Int(7).depth(), 0);
+    }
+
+    #[test]
+    fn test_streaming_compare() {
+        for left in SAMPLE {
+            for right in SAMPLE {
+                let expected = parse_line(left).unwrap().compare(&parse_line(right).unwrap());
+                assert_eq!(compare_packets(left.as_bytes(), right.as_bytes()).unwrap(), expected, "{} vs {}", left, right);
+            }
+        }
+        assert_eq!(compare_packets(b"[1]", b"[[[[1]]]]").unwrap(), Ordering::Equal);
+        assert_eq!(compare_packets(b"[[[[1]]],2]", b"[1,3]").unwrap(), Ordering::Less);
+        assert_eq!(compare_packets(b"[1]", b"[[]]").unwrap(), Ordering::Greater);
+        assert_eq!(compare_packets(b"[10,[-2]]", b"[10,[-1]]").unwrap(), Ordering::Less);
+    }
+
+    fn next_random(state: &mut u64, bound: u64) -> u64 {
+        *state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (*state >> 33) % bound
+    }
+
+    fn random_packet(state: &mut u64, depth: usize) -> String {
+        let mut items = Vec::new();
+        for _ in 0..next_random(state, 5) {
+            if depth < 12 && next_random(state, 3) == 0 {
+                items.push(random_packet(state, depth + 1));
+            } else {
+                items.push(next_random(state, 4).to_string());
+            }
+        }
+        format!("[{}]", items.join(","))
+    }
+
+    #[test]
+    fn test_streaming_matches_trees() {
+        // pseudo random packets, deeper than the puzzle's
+        let mut state: u64 = 13;
+        let packets: Vec<String> = (0..300).map(|_| random_packet(&mut state, 0)).collect();
+        let trees = parse_lines(packets.iter().cloned()).unwrap();
+        for (i, left) in packets.iter().enumerate() {
+            for (j, right) in packets.iter().enumerate().take(60) {
+                assert_eq!(compare_packets(left.as_bytes(), right.as_bytes()).unwrap(), trees[i].compare(&trees[j]), "{} vs {}", left, right);
+            }
+        }
+
+        let mut sorted_text = packets.clone();
+        sorted_text.sort_by(|a, b| compare_packets(a.as_bytes(), b.as_bytes()).unwrap());
+        let mut sorted_trees = trees.clone();
+        sorted_trees.sort_by(PacketTree::compare);
+        let reparsed = parse_lines(sorted_text.into_iter()).unwrap();
+        assert!(reparsed.iter().zip(&sorted_trees).all(|(a, b)| a.compare(b) == Ordering::Equal));
+    }
 }
@@ -1,6 +1,6 @@
 use std::{collections::HashSet, path::PathBuf, cmp};
 
-use clap::{value_parser, ArgMatches, Command};
+use clap::{builder::RangedU64ValueParser, value_parser, Arg, ArgAction, ArgMatches, Command};
 
 use crate::common;
 
@@ -10,58 +10,145 @@ use crate::common;
 //     Rock
 // }
 
-pub fn cli() -> Command {
-    Command::new("day14").about("Elvish cave collapse").arg(
-        clap::arg!(path: <PATH>)
-            .required(true)
-            .value_parser(value_parser!(std::path::PathBuf)),
-    )
+/// Sand poured from one or more sources. Each source keeps the path its
+/// last grain took, the next grain resumes from where that one came to rest.
+struct SandSimulation {
+    cave: HashSet<(usize, usize)>,
+    max_depth: usize,
+    /// Depth of the floor, without one sand below max_depth falls forever
+    floor: Option<usize>,
+    sources: Vec<(usize, usize)>,
+    paths: Vec<Vec<(usize, usize)>>,
+    grains: Vec<usize>,
 }
 
-pub fn handle(matches: &ArgMatches) {
-    let path = matches.get_one::<std::path::PathBuf>("path");
-    solve(path.unwrap().to_path_buf());
-}
+impl SandSimulation {
+    /// floor_offset counts from the deepest rock, the puzzle's floor is 2 below it
+    fn new(cave: HashSet<(usize, usize)>, max_depth: usize, floor_offset: Option<usize>, sources: Vec<(usize, usize)>) -> Self {
+        let count = sources.len();
+        Self {
+            cave,
+            max_depth,
+            floor: floor_offset.map(|offset| max_depth + offset),
+            sources,
+            paths: vec![Vec::new(); count],
+            grains: vec![0; count],
+        }
+    }
 
-pub fn solve(filepath: PathBuf) {
-    if let Ok(lines) = common::read_lines(&filepath) {
-        let mut counter = 0;
-        let (mut cave_map, depth) = parse_lines(lines.map(|l| l.unwrap()));
-        while let Some(new_sand) = calculate_sand_physics((500,0), &cave_map, depth, false) {
-            cave_map.insert(new_sand);
-            counter += 1;
+    fn is_free(&self, pos: (usize, usize)) -> bool {
+        self.floor != Some(pos.1) && !self.cave.contains(&pos)
+    }
+
+    /// Drops one grain from source, None if it fell out or the source is covered
+    fn drop_grain(&mut self, source: usize) -> Option<(usize, usize)> {
+        if self.paths[source].is_empty() {
+            if !self.is_free(self.sources[source]) {
+                return None;
+            }
+            self.paths[source].push(self.sources[source]);
         }
-        println!("{} units of sand before it noclips out of reality", counter);
         loop {
-            let new_sand = calculate_sand_physics((500,0), &cave_map, depth, true).unwrap();
-            cave_map.insert(new_sand);
-            counter += 1;
-            if new_sand == (500,0){
-                break;
+            let (x, y) = *self.paths[source].last().unwrap();
+            if self.floor.is_none() && y >= self.max_depth {
+                return None;
+            }
+            // sand cannot go left of x = 0
+            let moves = [Some(x), x.checked_sub(1), Some(x + 1)];
+            match moves.into_iter().flatten().map(|nx| (nx, y + 1)).find(|&next| self.is_free(next)) {
+                Some(next) => self.paths[source].push(next),
+                None => {
+                    self.cave.insert((x, y));
+                    self.grains[source] += 1;
+                    self.paths[source].pop();
+                    // paths through this cell are no longer valid past it. Sand
+                    // drops one row per step, so only one spot of a path can hold it
+                    for (path, &(_, source_y)) in self.paths.iter_mut().zip(&self.sources) {
+                        if let Some(i) = y.checked_sub(source_y).filter(|&i| path.get(i) == Some(&(x, y))) {
+                            path.truncate(i);
+                        }
+                    }
+                    return Some((x, y));
+                }
             }
         }
-        println!("{} units of sand before it fills sand source hole", counter);
     }
-}
 
-fn calculate_sand_physics(sand_pos: (usize, usize), cave_map: &HashSet<(usize, usize)>, max_depth: usize, magic_floor: bool) -> Option<(usize, usize)>{
-    if sand_pos.1 == max_depth && !magic_floor{
-        return None;
-    }
-    else if sand_pos.1 == max_depth+1 && magic_floor{
-        return Some(sand_pos);
-    }
-    else if !cave_map.contains(&(sand_pos.0, sand_pos.1+1)){
-        return calculate_sand_physics((sand_pos.0, sand_pos.1+1), cave_map, max_depth, magic_floor);
-    }
-    else if !cave_map.contains(&(sand_pos.0-1, sand_pos.1+1)){
-        return calculate_sand_physics((sand_pos.0-1, sand_pos.1+1), cave_map, max_depth, magic_floor);
+    /// Sources take turns dropping one grain until none of them can
+    fn run(&mut self) -> &[usize] {
+        let mut active = vec![true; self.sources.len()];
+        while active.contains(&true) {
+            for (source, active) in active.iter_mut().enumerate() {
+                if *active && self.drop_grain(source).is_none() {
+                    *active = false;
+                }
+            }
+        }
+        &self.grains
     }
-    else if !cave_map.contains(&(sand_pos.0+1, sand_pos.1+1)){
-        return calculate_sand_physics((sand_pos.0+1, sand_pos.1+1), cave_map, max_depth, magic_floor);
+}
+
+fn parse_source(text: &str) -> Result<(usize, usize), String> {
+    text.split_once(',')
+        .and_then(|(x, y)| Some((x.trim().parse().ok()?, y.trim().parse().ok()?)))
+        .ok_or_else(|| format!("'{}' is not an x,y position", text))
+}
+
+pub fn cli() -> Command {
+    Command::new("day14")
+        .about("Elvish cave collapse")
+        .arg(
+            clap::arg!(path: <PATH>)
+                .required(true)
+                .value_parser(value_parser!(std::path::PathBuf)),
+        )
+        .arg(
+            Arg::new("source")
+                .short('s')
+                .long("source")
+                .help("Where sand comes from, x,y, may be repeated")
+                .action(ArgAction::Append)
+                .default_value("500,0")
+                .value_parser(parse_source),
+        )
+        .arg(
+            Arg::new("floor")
+                .short('f')
+                .long("floor")
+                .help("Floor distance below the deepest rock for the second run")
+                .default_value("2")
+                .value_parser(RangedU64ValueParser::<usize>::new().range(1..)),
+        )
+}
+
+pub fn handle(matches: &ArgMatches) {
+    let path = matches.get_one::<std::path::PathBuf>("path");
+    let sources: Vec<(usize, usize)> = matches.get_many::<(usize, usize)>("source").unwrap().copied().collect();
+    let floor = *matches.get_one::<usize>("floor").unwrap();
+    solve(path.unwrap().to_path_buf(), &sources, floor);
+}
+
+pub fn solve(filepath: PathBuf, sources: &[(usize, usize)], floor_offset: usize) {
+    if let Ok(lines) = common::read_lines(&filepath) {
+        let (cave_map, depth) = parse_lines(lines.map(|l| l.unwrap()));
+
+        let mut simulation = SandSimulation::new(cave_map.clone(), depth, None, sources.to_vec());
+        let grains = simulation.run();
+        println!("{} units of sand before it noclips out of reality", grains.iter().sum::<usize>());
+        print_per_source(sources, grains);
+
+        let mut simulation = SandSimulation::new(cave_map, depth, Some(floor_offset), sources.to_vec());
+        let grains = simulation.run();
+        println!("{} units of sand before it fills sand source hole", grains.iter().sum::<usize>());
+        print_per_source(sources, grains);
     }
-    else{
-        return Some(sand_pos);
+}
+
+fn print_per_source(sources: &[(usize, usize)], grains: &[usize]) {
+    if sources.len() > 1 {
+        for (source, count) in sources.iter().zip(grains) {
+            println!("  source {},{}: {} units", source.0, source.1, count);
+        }
     }
 }
 
@@ -111,7 +198,46 @@ fn parse_line(line: &str) -> HashSet<(usize, usize)> {
 mod tests {
     use std::collections::HashSet;
 
-    use super::parse_line;
+    use super::{parse_line, parse_lines, parse_source, SandSimulation};
+
+    fn sample() -> (HashSet<(usize, usize)>, usize) {
+        let lines = ["498,4 -> 498,6 -> 496,6", "503,4 -> 502,4 -> 502,9 -> 494,9"];
+        parse_lines(lines.iter().map(|l| l.to_string()))
+    }
+
+    /// Straightforward simulation, every grain falls from its source
+    fn reference(cave: &HashSet<(usize, usize)>, depth: usize, floor: Option<usize>, sources: &[(usize, usize)]) -> Vec<usize> {
+        let mut cave = cave.clone();
+        let mut grains = vec![0; sources.len()];
+        let mut active = vec![true; sources.len()];
+        let blocked = |cave: &HashSet<(usize, usize)>, pos: (usize, usize)| cave.contains(&pos) || floor.is_some_and(|f| pos.1 == depth + f);
+        while active.contains(&true) {
+            for (i, &source) in sources.iter().enumerate() {
+                if !active[i] {
+                    continue;
+                }
+                if blocked(&cave, source) {
+                    active[i] = false;
+                    continue;
+                }
+                let (mut x, mut y) = source;
+                loop {
+                    if floor.is_none() && y >= depth {
+                        active[i] = false;
+                        break;
+                    }
+                    if let Some(nx) = [x, x - 1, x + 1].into_iter().find(|&nx| !blocked(&cave, (nx, y + 1))) {
+                        (x, y) = (nx, y + 1);
+                    } else {
+                        cave.insert((x, y));
+                        grains[i] += 1;
+                        break;
+                    }
+                }
+            }
+        }
+        grains
+    }
 
     #[test]
     fn test_parse_line() {
@@ -123,4 +249,50 @@ mod tests {
         c2.sort();
         assert_eq!(s2, c2);
     }
+
+    #[test]
+    fn test_sample() {
+        let (cave, depth) = sample();
+        assert_eq!(SandSimulation::new(cave.clone(), depth, None, vec![(500, 0)]).run(), [24]);
+        assert_eq!(SandSimulation::new(cave, depth, Some(2), vec![(500, 0)]).run(), [93]);
+    }
+
+    #[test]
+    fn test_sources_and_floors() {
+        let (cave, depth) = sample();
+        let setups = [
+            (None, vec![(500, 0), (497, 2)]),
+            (Some(2), vec![(500, 0), (497, 2)]),
+            (Some(1), vec![(500, 0)]),
+            (Some(5), vec![(503, 0), (490, 3), (500, 0)]),
+            (Some(3), vec![(500, 0), (500, 0)]),
+        ];
+        for (floor, sources) in setups {
+            let expected = reference(&cave, depth, floor, &sources);
+            let mut simulation = SandSimulation::new(cave.clone(), depth, floor, sources.clone());
+            assert_eq!(simulation.run(), expected.as_slice(), "floor {:?} sources {:?}", floor, sources);
+        }
+        // a source inside rock drops nothing
+        let mut simulation = SandSimulation::new(cave, depth, Some(2), vec![(498, 5), (500, 0)]);
+        assert_eq!(simulation.run(), [0, 93]);
+    }
+
+    #[test]
+    fn test_deep_cave() {
+        // a single ledge far down, grains pile up into a triangle on the floor
+        let cave = HashSet::from([(500, 200000)]);
+        let mut simulation = SandSimulation::new(cave.clone(), 200000, None, vec![(500, 0)]);
+        assert_eq!(simulation.run(), [0]);
+        let mut simulation = SandSimulation::new(cave, 200000, Some(2), vec![(500, 199990)]);
+        // 12 rows of a triangle minus the ledge, sand slides in under it
+        assert_eq!(simulation.run(), [12 * 12 - 1]);
+    }
+
+    #[test]
+    fn test_parse_source() {
+        assert_eq!(parse_source("500,0"), Ok((500, 0)));
+        assert_eq!(parse_source(" 3 , 4"), Ok((3, 4)));
+        assert!(parse_source("500").is_err());
+        assert!(parse_source("a,1").is_err());
+    }
 }
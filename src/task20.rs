@@ -48,7 +48,7 @@ fn mix(original: &Vec<Cell>) -> Vec<Cell> {
         let mut current_index = 0;
         //let mut cell;
         for j in 0..length {
-            if acc[j].og_pos == i.try_into().unwrap() {
+            if acc[j].og_pos == isize::try_from(i).unwrap() {
                 current_index = j;
                 break;
             }